@@ -0,0 +1,126 @@
+//! Async wrapper that awaits the INT pin instead of polling the ENC28J60
+
+use embedded_hal::blocking;
+use embedded_hal::digital::v2::OutputPin;
+use embedded_hal_async::digital::Wait;
+use smoltcp::phy::{Device as SmolDevice, DeviceCapabilities};
+
+use crate::{Error, Result, RxToken, SmolEnc28j60, Tokens, TxToken, BUFFER_SIZE};
+
+/// [SmolEnc28j60] paired with an INT pin that can be awaited, for use on async executors such as
+/// embassy
+///
+/// The INT pin is taken separately from the driver, so the driver can be built with
+/// `enc28j60::Unconnected` in its place. Only waiting for frames is async, SPI transfers are still
+/// blocking as the driver is.
+pub struct AsyncSmolEnc28j60<Spi, Ncs, Int, Reset, Irq, const N: usize = BUFFER_SIZE>
+where
+    Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8>,
+    Ncs: OutputPin,
+    Int: enc28j60::IntPin,
+    Reset: enc28j60::ResetPin,
+    Irq: Wait,
+{
+    device: SmolEnc28j60<Spi, Ncs, Int, Reset, N>,
+    irq: Irq,
+}
+
+impl<Spi, Ncs, Int, Reset, Irq, const N: usize> AsyncSmolEnc28j60<Spi, Ncs, Int, Reset, Irq, N>
+where
+    Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8>,
+    Ncs: OutputPin,
+    Int: enc28j60::IntPin,
+    Reset: enc28j60::ResetPin,
+    Irq: Wait,
+{
+    /// Pair a device with the pin connected to the ENC28J60 INT output
+    pub fn new(device: SmolEnc28j60<Spi, Ncs, Int, Reset, N>, irq: Irq) -> Self {
+        AsyncSmolEnc28j60 { device, irq }
+    }
+
+    /// Wait until a received frame is pending
+    ///
+    /// The ENC28J60 is set to assert INT while frames are pending (EIE.INTIE, EIE.PKTIE), which is
+    /// reapplied on every call so it survives [SmolEnc28j60::reset]. Returns straight away if a
+    /// frame is already pending. The other interrupt sources, such as the link change interrupt,
+    /// are masked while waiting so they can't hold INT asserted and have this return without a
+    /// frame, and the transmit and receive error flags are cleared as by
+    /// [SmolEnc28j60::clear_interrupts]. They are enabled again once the wait is over, but stay
+    /// masked if the future is dropped before then, until the next wait or
+    /// [SmolEnc28j60::reset].
+    pub async fn wait_for_packet(&mut self) -> Result<()> {
+        let lower = &self.device.device;
+        lower.mask_to_packet_interrupt()?;
+        let result = self
+            .irq
+            .wait_for_low()
+            .await
+            .map_err(|_| lower.fail(Error::InterruptPin));
+        lower.unmask_interrupts()?;
+        result
+    }
+
+    /// Wait for a received frame, then return the tokens smoltcp would be handed for it
+    ///
+    /// EPKTCNT is only read once the INT pin is asserted, see
+    /// [AsyncSmolEnc28j60::wait_for_packet] for how the ENC28J60 is set up to assert it, rather
+    /// than polled in a busy loop. While frames are queued this returns straight away without
+    /// awaiting the pin. [Error::Illegal] is returned while reception is disabled
+    /// or the ENC28J60 is powered down, where no frame would ever arrive, or if the frame can't be
+    /// handed out, such as when the device is in use.
+    pub async fn receive_async(&mut self) -> Result<Tokens<'_, Spi, Ncs, Int, Reset, N>> {
+        loop {
+            let lower = &self.device.device;
+            if lower.powered_down.get() || !lower.rx_enabled.get() {
+                return Err(Error::Illegal);
+            }
+            if lower.rx_pending() {
+                break;
+            }
+            self.wait_for_packet().await?;
+        }
+
+        self.device.rx_tokens().ok_or(Error::Illegal)
+    }
+
+    /// The wrapped device, for the configuration and status methods of [SmolEnc28j60]
+    pub fn inner(&self) -> &SmolEnc28j60<Spi, Ncs, Int, Reset, N> {
+        &self.device
+    }
+
+    /// The wrapped device, for the configuration and status methods of [SmolEnc28j60]
+    pub fn inner_mut(&mut self) -> &mut SmolEnc28j60<Spi, Ncs, Int, Reset, N> {
+        &mut self.device
+    }
+
+    /// Unwrap the device and INT pin
+    pub fn into_inner(self) -> (SmolEnc28j60<Spi, Ncs, Int, Reset, N>, Irq) {
+        (self.device, self.irq)
+    }
+}
+
+impl<'a, Spi, Ncs, Int, Reset, Irq, const N: usize> SmolDevice<'a>
+    for AsyncSmolEnc28j60<Spi, Ncs, Int, Reset, Irq, N>
+where
+    Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8> + 'a,
+    Ncs: OutputPin + 'a,
+    Int: enc28j60::IntPin + 'a,
+    Reset: enc28j60::ResetPin + 'a,
+    Irq: Wait + 'a,
+{
+    type RxToken = RxToken<'a, Spi, Ncs, Int, Reset, N>;
+
+    type TxToken = TxToken<'a, Spi, Ncs, Int, Reset, N>;
+
+    fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
+        self.device.receive()
+    }
+
+    fn transmit(&'a mut self) -> Option<Self::TxToken> {
+        self.device.transmit()
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        self.device.capabilities()
+    }
+}
@@ -0,0 +1,69 @@
+//! Software verification of the IPv4, UDP and TCP checksums of received frames
+
+/// EtherType of IPv4
+const ETHERTYPE_IPV4: u16 = 0x0800;
+/// Size of the Ethernet header, without a VLAN tag
+const ETHERNET_HEADER_SIZE: usize = 14;
+const PROTOCOL_TCP: u8 = 6;
+const PROTOCOL_UDP: u8 = 17;
+
+/// Whether the checksums of `frame` are valid
+///
+/// Only IPv4 frames are checked, along with the TCP or UDP segment they carry unless the datagram
+/// is fragmented or a UDP checksum is left out. Anything else, including truncated frames, passes
+/// and is left to smoltcp.
+pub(crate) fn verify(frame: &[u8]) -> bool {
+    let ip = match frame.get(ETHERNET_HEADER_SIZE..) {
+        Some(ip) if u16::from_be_bytes([frame[12], frame[13]]) == ETHERTYPE_IPV4 => ip,
+        _ => return true,
+    };
+    if ip.len() < 20 || ip[0] >> 4 != 4 {
+        return true;
+    }
+
+    let header_len = usize::from(ip[0] & 0x0F) * 4;
+    let total_len = usize::from(u16::from_be_bytes([ip[2], ip[3]]));
+    if header_len < 20 || total_len < header_len || total_len > ip.len() {
+        return true;
+    }
+    if fold(sum(&ip[..header_len], 0)) != 0xFFFF {
+        return false;
+    }
+
+    // Fragments are checked by smoltcp once reassembled, if at all
+    let fragmented = u16::from_be_bytes([ip[6], ip[7]]) & 0x3FFF != 0;
+    let protocol = ip[9];
+    let segment = &ip[header_len..total_len];
+    let checked = match protocol {
+        PROTOCOL_TCP => segment.len() >= 20,
+        PROTOCOL_UDP => segment.len() >= 8 && segment[6..8] != [0, 0],
+        _ => false,
+    };
+    if fragmented || !checked {
+        return true;
+    }
+
+    // Pseudo header of the source and destination addresses, protocol and segment length
+    let pseudo = sum(&ip[12..20], u32::from(protocol) + segment.len() as u32);
+    fold(sum(segment, pseudo)) == 0xFFFF
+}
+
+/// Add the 16 bit big endian words of `data` to `sum`, padding an odd byte out with zero
+fn sum(data: &[u8], sum: u32) -> u32 {
+    let mut chunks = data.chunks_exact(2);
+    let mut sum = chunks.by_ref().fold(sum, |sum, word| {
+        sum + u32::from(u16::from_be_bytes([word[0], word[1]]))
+    });
+    if let [last] = chunks.remainder() {
+        sum += u32::from(*last) << 8;
+    }
+    sum
+}
+
+/// One's complement sum of the 16 bit words added into `sum`
+fn fold(mut sum: u32) -> u16 {
+    while sum >> 16 != 0 {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    sum as u16
+}
@@ -12,254 +12,3670 @@
 //!     - `no_std`
 //!     - zero (runtime) allocations
 //!     - uses [embedded_hal] types for hardware abstraction
+//!     - optional logging through `defmt` or `log` with the `defmt` and `log` features
+//!     - optional `AsyncSmolEnc28j60` that awaits the INT pin through `embedded-hal-async`, with the `async` feature
+//!     - optional software RX ring buffering frames ahead of smoltcp, see `SmolEnc28j60::set_rx_ring`, with the `heapless` feature
+//!     - optional pool of RX buffers for bursts of RX tokens, see `SmolEnc28j60::set_rx_pool`, with the `heapless` feature
+//!     - raw control register access through `SmolEnc28j60::read_reg` and `SmolEnc28j60::write_reg`, with the `unstable-registers` feature
+//!     - SPI transfer accounting through `SmolEnc28j60::spi_stats`, with the `metrics` feature
+//!     - software IPv4, UDP and TCP checksum verification of received frames, see `SmolEnc28j60::set_drop_bad_checksums`, with the `verify-checksums` feature
 //!
 //! Limitations:
-//!     - RX/TX are limited to a single buffer of size (1518 - 4)
-//!     - Only one RX/TX operation at a time, if another operation is attempted while one is in progress then [smoltcp::Error::Illegal] will be returned
-//!     - smoltcp will always be requested to perform checksum checking on behalf of the ENC28J60 device
+//!     - RX and TX each have a buffer of `N` bytes, (1518 - 4) by default, see [SmolEnc28j60::new] and [SmolEnc28j60::with_buffer]
+//!     - One RX and one TX operation can be in progress at a time, if another operation of the same kind is attempted while one is in progress then [smoltcp::Error::Exhausted] will be returned
+//!     - smoltcp is requested to perform checksum checking on behalf of the ENC28J60 device by default, see [SmolEnc28j60::set_checksum_caps]
 
-use core::cell::{RefCell, RefMut};
+#[cfg(test)]
+extern crate std;
+
+use core::cell::{Cell, RefCell, RefMut};
+use core::ops::{Deref, DerefMut};
 
 use embedded_hal::blocking;
+use embedded_hal::blocking::delay::DelayUs;
 use embedded_hal::digital::v2::OutputPin;
 use enc28j60::{Enc28j60, CRC_SZ, MAX_FRAME_LENGTH};
 
-use smoltcp::phy::{self, Device as SmolDevice, DeviceCapabilities};
+use smoltcp::phy::{self, ChecksumCapabilities, Device as SmolDevice, DeviceCapabilities};
+use smoltcp::time::Instant;
+
+#[macro_use]
+mod fmt;
+#[cfg(feature = "async")]
+mod asynch;
+#[cfg(feature = "verify-checksums")]
+mod checksum;
+#[cfg(test)]
+mod mock;
+#[cfg(feature = "heapless")]
+mod pool;
+mod register;
+#[cfg(feature = "heapless")]
+mod ring;
+mod shared;
+mod split;
+#[cfg(test)]
+mod tests;
+
+#[cfg(feature = "async")]
+pub use asynch::AsyncSmolEnc28j60;
+#[cfg(not(feature = "unstable-registers"))]
+use register::{Bank, Register};
+#[cfg(feature = "unstable-registers")]
+pub use register::{Bank, Register};
+#[cfg(feature = "heapless")]
+pub use ring::Frame;
+pub use shared::SharedSmolEnc28j60;
+pub use split::{RxHalf, TxHalf};
+
+#[cfg(feature = "heapless")]
+use pool::BufferPool;
+#[cfg(feature = "heapless")]
+use ring::FrameRing;
+
+use register::{
+    BankedEnc28j60, PhyRegister, Pointer, Registers, ECON1_RXEN, ECON2_PKTDEC, ECON2_PWRSV,
+    ECON2_VRPS, EFLOCON_FCEN_PAUSE, EFLOCON_FCEN_RELEASE, EIE_INTIE, EIE_LINKIE, EIR_DMAIF,
+    EIR_LINKIF, EIR_PKTIF, EIR_RXERIF, EIR_TXERIF, EIR_TXIF, ERXFCON_BCEN, ERXFCON_CRCEN,
+    ERXFCON_HTEN, ERXFCON_MCEN, ERXFCON_UCEN, MACON1_MARXEN, MACON3_FRMLNEN, MACON3_FULDPX,
+    MACON3_PADCFG0, MACON3_TXCRCEN, MACON4_DEFER, PHCON1_PDPXMD, PHCON1_PLOOPBK, PHCON2_HDLDIS,
+    PHIE_PGEIE, PHIE_PLNKIE, PHIR_PLNKIF, PHLCON_RESERVED, PHLCON_STRCH, TSV_SIZE,
+};
+
+/// Maximum message size
+const BUFFER_SIZE: usize = (MAX_FRAME_LENGTH - CRC_SZ) as usize;
+
+/// Size of the next packet pointer and receive status vector preceding each received frame
+const RX_HEADER_SIZE: usize = 6;
+
+/// Space a full frame takes up in the RX buffer, with its header and CRC
+const RX_FRAME_SIZE: usize = RX_HEADER_SIZE + MAX_FRAME_LENGTH as usize;
+
+/// Space needed for transmitting, a control byte and a full frame followed by its status vector
+const TX_BUFFER_SIZE: usize = 1 + BUFFER_SIZE + TSV_SIZE;
+
+/// Last address of the 8 KB buffer memory
+const BUFFER_END: u16 = 0x1FFF;
+
+/// Start of the RX buffer
+///
+/// Errata #5: the receive hardware can corrupt the RX buffer unless it starts at 0x0000
+const ERRATA_RX_START: u16 = 0x0000;
+
+/// Size of an 802.1Q tag, see [SmolEnc28j60::set_vlan]
+const VLAN_TAG_SIZE: usize = 4;
+
+/// EtherType identifying an 802.1Q tag (TPID)
+const VLAN_TPID: u16 = 0x8100;
+
+/// Number of times the MAC address is repeated in a wake-on-LAN magic packet, after 6 bytes of
+/// 0xFF
+const MAGIC_PACKET_REPEATS: usize = 16;
+
+/// Pause time sent with pause frames by [FlowControl::PauseOnFull], the EPAUS reset value, in
+/// units of 512 bit times
+const DEFAULT_PAUSE_TIME: u16 = 0x1000;
+
+/// Size of the receive buffer set up by [SmolEnc28j60::init]
+///
+/// This leaves 2 KB of the 8 KB buffer memory for transmitting, enough for a full frame along with
+/// its control byte and status vector
+const INIT_RX_BUFFER_SIZE: u16 = 6 * 1024;
+
+/// Time the reset pin is held, and then given to the ENC28J60 to come out of reset
+const RESET_DELAY_US: u16 = 50;
+
+/// Time given to the ENC28J60 to come out of a system reset command
+///
+/// Errata #2: ESTAT.CLKRDY can't be relied on after a system reset command, so wait at least 1 ms
+const SYSTEM_RESET_DELAY_US: u16 = 1000;
+
+/// Default number of ECON1 polls before a transmission is aborted, see
+/// [SmolEnc28j60::set_transmit_timeout]
+///
+/// This is well over the 1.2 ms a full frame takes on the wire, leaving room for collisions and
+/// backoff in half duplex
+const TRANSMIT_POLLS: u32 = 100_000;
+
+/// Length of the frame sent by [SmolEnc28j60::self_test], the shortest frame without padding
+const SELF_TEST_FRAME_SIZE: usize = 60;
+
+/// EtherType of the frame sent by [SmolEnc28j60::self_test], the IEEE local experimental type
+const SELF_TEST_ETHERTYPE: u16 = 0x88B5;
+
+/// Number of EPKTCNT polls while waiting for the frame sent by [SmolEnc28j60::self_test]
+const SELF_TEST_POLLS: usize = 10_000;
+
+/// Wrapper for enc28j60 that implements the smoltcp Device trait
+///
+/// Boards without a GPIO for the reset line can use [enc28j60::Unconnected] for `Reset` and reset
+/// the ENC28J60 over SPI instead, see [SmolEnc28j60::reset_with_command].
+pub struct SmolEnc28j60<Spi, Ncs, Int, Reset, const N: usize = BUFFER_SIZE>
+where
+    Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8>,
+    Ncs: OutputPin,
+    Int: enc28j60::IntPin,
+    Reset: enc28j60::ResetPin,
+{
+    device: InnerEnc28j60<Spi, Ncs, Int, Reset, N>,
+    checksum: ChecksumCapabilities,
+    /// Set through [SmolEnc28j60::set_max_burst_size], otherwise the full frames fitting in the RX
+    /// buffer are reported
+    max_burst_size: Option<Option<usize>>,
+    mtu: usize,
+    medium: phy::Medium,
+}
+
+impl<Spi, Ncs, Int, Reset> From<Enc28j60<Spi, Ncs, Int, Reset>>
+    for SmolEnc28j60<Spi, Ncs, Int, Reset>
+where
+    Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8>,
+    Ncs: OutputPin,
+    Int: enc28j60::IntPin,
+    Reset: enc28j60::ResetPin,
+{
+    fn from(enc: Enc28j60<Spi, Ncs, Int, Reset>) -> Self {
+        SmolEnc28j60::new(enc, false)
+    }
+}
+
+impl<Spi, Ncs, Int, Reset, const N: usize> SmolEnc28j60<Spi, Ncs, Int, Reset, N>
+where
+    Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8>,
+    Ncs: OutputPin,
+    Int: enc28j60::IntPin,
+    Reset: enc28j60::ResetPin,
+{
+    /// Longest frame the ENC28J60 can transmit and receive without its CRC, `1518 - 4` bytes
+    ///
+    /// This is the default size `N` of the frame buffers, for sizing buffers of the application to
+    /// match rather than hardcoding it.
+    pub const MTU: usize = BUFFER_SIZE;
+
+    /// Wrap an ENC28J60, optionally preserving the CRC of received frames
+    ///
+    /// When `preserve_crc` is set, received frames are handed to smoltcp with their 4 byte CRC
+    /// still attached. This is for tooling that needs the frame exactly as it appeared on the wire,
+    /// such as when inspecting VLAN-tagged traffic. Transmitted frames are still limited to
+    /// `1518 - 4` bytes as the ENC28J60 appends the CRC itself.
+    ///
+    /// An RX buffer the driver left away from 0x0000 is moved there for errata #5, see
+    /// [SmolEnc28j60::set_rx_buffer_size].
+    ///
+    /// `N` sets the size of each of the RX and TX frame buffers. Received frames longer than `N` are
+    /// truncated, so use `N = 1518` to receive full frames with their CRC, or a smaller `N` to save
+    /// RAM on devices that only see small frames.
+    pub fn new(enc: Enc28j60<Spi, Ncs, Int, Reset>, preserve_crc: bool) -> Self {
+        Self::with_frame_buffers(
+            enc,
+            preserve_crc,
+            FrameBuffer::Embedded(AlignedBuffer::ZEROED),
+            FrameBuffer::Embedded(AlignedBuffer::ZEROED),
+        )
+    }
+
+    fn with_frame_buffers(
+        enc: Enc28j60<Spi, Ncs, Int, Reset>,
+        preserve_crc: bool,
+        rx_buffer: FrameBuffer<N>,
+        tx_buffer: FrameBuffer<N>,
+    ) -> Self {
+        let device = InnerEnc28j60::new(enc, preserve_crc, rx_buffer, tx_buffer);
+        let mtu = device.capacity.min(BUFFER_SIZE);
+        SmolEnc28j60 {
+            device,
+            checksum: ChecksumCapabilities::default(),
+            max_burst_size: None,
+            mtu,
+            medium: phy::Medium::Ethernet,
+        }
+    }
+
+    /// Initialise an ENC28J60 and wrap it, ready to be handed to smoltcp
+    ///
+    /// The driver is set up with 6 KB of the buffer memory for receiving and the rest for
+    /// transmitting, with `mac` as its MAC address and reception enabled. [Error::NotReady] is
+    /// returned if the oscillator doesn't report being ready afterwards (ESTAT.CLKRDY), as the
+    /// configuration written before it started is lost.
+    pub fn init<D>(
+        spi: Spi,
+        ncs: Ncs,
+        int: Int,
+        reset: Reset,
+        delay: &mut D,
+        mac: [u8; 6],
+    ) -> Result<Self>
+    where
+        D: DelayUs<u8>,
+    {
+        let mut enc = Enc28j60::new(spi, ncs, int, reset, delay, INIT_RX_BUFFER_SIZE, mac)
+            .map_err(|_| Error::SpiTransfer)?;
+        enc.wait_clock_ready()?;
+        Ok(Self::new(enc, false))
+    }
+
+    /// Wrap an ENC28J60 after checking that it reports a known silicon revision
+    ///
+    /// This catches a device that isn't responding, such as from miswired SPI, before it is handed
+    /// to smoltcp. The oscillator is waited on first (ESTAT.CLKRDY), for a bounded number of polls,
+    /// as register accesses are ignored until it has started after power on.
+    ///
+    /// The ENC28J60 needs SPI mode 0, which embedded-hal doesn't let the wrapper check. Other modes
+    /// or too fast a clock can mostly work and corrupt the occasional transfer, so test patterns are
+    /// also written to a register and read back, returning [RevisionError::SpiConfig] if they don't
+    /// match.
+    pub fn try_new(
+        mut enc: Enc28j60<Spi, Ncs, Int, Reset>,
+    ) -> core::result::Result<Self, RevisionError> {
+        enc.wait_clock_ready().map_err(|error| match error {
+            Error::NotReady => RevisionError::NotReady,
+            _ => RevisionError::Spi,
+        })?;
+        let revision = enc.read(Register::EREVID).map_err(|_| RevisionError::Spi)?;
+        if let Revision::Unknown(revision) = Revision::from_erevid(revision) {
+            return Err(RevisionError::Unknown(revision));
+        }
+        if !enc.spi_handshake().map_err(|_| RevisionError::Spi)? {
+            return Err(RevisionError::SpiConfig);
+        }
+
+        Ok(Self::new(enc, false))
+    }
+
+    /// MAC address the ENC28J60 is configured with
+    ///
+    /// The address is read back from the MAADR registers. If a token is currently using the device
+    /// then the address from the last successful read is returned instead.
+    pub fn mac_address(&self) -> [u8; 6] {
+        self.device.mac_address()
+    }
+
+    /// Change the station MAC address (MAADR1..MAADR6)
+    ///
+    /// Frames are filtered on and transmitted from the new address from now on, and it is kept
+    /// across [SmolEnc28j60::reset]. Reception is paused while the address is written, so no frame
+    /// is filtered on a partial address. [Error::Illegal] is returned if a token is in use or a
+    /// frame is being transmitted, where changing the address would corrupt traffic.
+    pub fn set_mac_address(&mut self, mac: [u8; 6]) -> Result<()> {
+        self.device.set_mac_address(mac)
+    }
+
+    /// Silicon revision of the ENC28J60 (EREVID)
+    ///
+    /// If a token is currently using the device then the revision read at construction is returned
+    /// instead.
+    pub fn revision(&self) -> u8 {
+        self.device.revision()
+    }
+
+    /// Silicon revision of the ENC28J60, as for [SmolEnc28j60::revision]
+    pub fn revision_enum(&self) -> Revision {
+        Revision::from_erevid(self.revision())
+    }
+
+    /// Whether the PHY reports the link as up
+    ///
+    /// Reading the link status costs one PHY register read (PHSTAT2.LSTAT) over SPI. If a token is
+    /// currently using the device then the last known link status is returned instead.
+    pub fn link_up(&self) -> bool {
+        self.device.link_up()
+    }
+
+    /// Speed of the link, always [LinkSpeed::TenBaseT] as that is all the ENC28J60 supports
+    ///
+    /// This is for showing link information uniformly alongside other network interfaces, see
+    /// [SmolEnc28j60::link_info].
+    pub fn link_speed(&self) -> LinkSpeed {
+        LinkSpeed::TenBaseT
+    }
+
+    /// Link status, duplex mode and speed of the PHY, from a single read of PHSTAT2
+    ///
+    /// If a token is currently using the device then the last known link status and duplex mode
+    /// are returned instead.
+    pub fn link_info(&self) -> LinkInfo {
+        self.device.link_info()
+    }
+
+    /// Assert the INT pin when the link goes up or down (PHIE.PLNKIE, EIE.LINKIE)
+    ///
+    /// Check [SmolEnc28j60::link_changed] when INT fires to tell a link change apart from a
+    /// received frame. The interrupt is enabled again after [SmolEnc28j60::reset].
+    pub fn enable_link_change_interrupt(&mut self) -> Result<()> {
+        self.device.enable_link_change_interrupt()
+    }
+
+    /// Whether the link has gone up or down since this was last called (PHIR.PLNKIF)
+    ///
+    /// Reading PHIR clears the flag and releases the INT pin. Returns `false` if a token is
+    /// currently using the device, the flag is then left for the next call.
+    pub fn link_changed(&self) -> bool {
+        self.device.link_changed()
+    }
+
+    /// Check whether the link went up or down since the last call, and bring the MAC back up
+    /// once the cable is reconnected
+    ///
+    /// Meant to be called once per main loop iteration. On [LinkEvent::LinkUp] the duplex mode is
+    /// programmed again and reception enabled, unless powered down or disabled through
+    /// [SmolEnc28j60::set_rx_enabled], so the interface keeps working across a physical
+    /// disconnect. A link that went down and up again between two calls is reported as
+    /// [LinkEvent::LinkUp]. Reading the change flag (PHIR.PLNKIF) clears it, so this and
+    /// [SmolEnc28j60::link_changed] shouldn't both be used.
+    pub fn poll_link(&mut self) -> Result<LinkEvent> {
+        self.device.poll_link()
+    }
+
+    /// Number of received frames waiting in the ENC28J60 buffer (EPKTCNT)
+    ///
+    /// The count saturates, so 255 means at least 255 frames are waiting. Returns 0 if the count
+    /// can't be read because a token is using the device or SPI fails.
+    pub fn pending_packets(&self) -> u8 {
+        self.device.pending_packets()
+    }
+
+    /// Bytes of the RX buffer taken up by received frames, from ERXWRPT and ERXRDPT
+    ///
+    /// This is approximate, as the ENC28J60 moves the write pointer as frames are received, and is
+    /// meant for coarse backpressure such as raising the SPI clock or dropping non-critical traffic
+    /// as the buffer fills. Returns 0 if the pointers can't be read because a token is using the
+    /// device or SPI fails.
+    pub fn rx_fifo_bytes(&self) -> u16 {
+        self.device.rx_fifo_bytes()
+    }
+
+    /// Whether the ENC28J60 is asserting its INT pin
+    ///
+    /// The driver enables the packet interrupt when it is built with a connected INT pin, so this
+    /// is cheaper than [SmolEnc28j60::pending_packets] as it samples a GPIO rather than reading
+    /// EPKTCNT over SPI. Returns `false` if a token is currently using the device.
+    ///
+    /// To avoid spinning on SPI while idle, sleep until INT fires (such as with a GPIO interrupt and
+    /// `wfi`), then poll the smoltcp interface until [SmolEnc28j60::pending_packets] returns 0 before
+    /// sleeping again.
+    pub fn interrupt_pending(&self) -> bool {
+        self.device
+            .device()
+            .map(|mut device| device.interrupt_pending())
+            .unwrap_or(false)
+    }
+
+    /// Clear the serviced interrupt flags in EIR so the INT pin is released
+    ///
+    /// A receive buffer overflow is counted in [SmolEnc28j60::stats] before its flag is cleared.
+    /// The packet flag (EIR.PKTIF) can't be cleared directly, it clears once every pending frame
+    /// has been received.
+    pub fn clear_interrupts(&mut self) -> Result<()> {
+        self.device.clear_interrupts()
+    }
+
+    /// Read which interrupt sources fired (EIR) and clear their flags, so the INT pin is released
+    ///
+    /// This is a single entry point for an interrupt handler: a flag left set keeps INT asserted,
+    /// and firmware waiting on it would spin without making progress. The returned status tells
+    /// the caller what to handle. As for [SmolEnc28j60::clear_interrupts], a receive buffer
+    /// overflow is counted in [SmolEnc28j60::stats] and the packet flag stays set until every
+    /// pending frame has been received. The link flag is cleared by reading PHIR, which also clears
+    /// the flag [SmolEnc28j60::link_changed] reads, so follow a link change with
+    /// [SmolEnc28j60::poll_link], which compares the link status with the last one seen instead.
+    pub fn service_interrupts(&mut self) -> Result<InterruptStatus> {
+        self.device.service_interrupts()
+    }
+
+    /// Read the next received frame straight into `buf`, without going through smoltcp
+    ///
+    /// Returns the length of the frame, or 0 if no frames are waiting. Frames longer than `buf` are
+    /// truncated. The frame counts towards [SmolEnc28j60::stats] as if smoltcp received it.
+    pub fn read_frame(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self.device.receive(buf) {
+            Err(Error::NoFrame | Error::Filtered) => Ok(0),
+            result => result,
+        }
+    }
+
+    /// Receive the next frame straight into `dst` for forwarding, returning its length or 0 if no
+    /// frames are waiting
+    ///
+    /// This is [SmolEnc28j60::read_frame] under the name bridges look for: the frame is read over
+    /// SPI into `dst` without passing through the RX frame buffer or smoltcp. To forward between
+    /// two interfaces with no copy beyond the SPI transfers, receive into a buffer of
+    /// [SmolEnc28j60::MTU] bytes and pass the frame to [SmolEnc28j60::write_frame] of the other
+    /// device, which writes it straight into that ENC28J60.
+    pub fn receive_into(&mut self, dst: &mut [u8]) -> Result<usize> {
+        self.read_frame(dst)
+    }
+
+    /// Receive the next frame straight into `ring`, returning its length or 0 if no frames are
+    /// waiting
+    ///
+    /// This is for applications that keep their own byte ring, such as the payload ring of a
+    /// smoltcp `PacketBuffer`, and want received frames read over SPI straight into it rather than
+    /// copied out of an RX token. A smoltcp `RingBuffer<u8>` is adapted to [RingWriter] by a
+    /// wrapper mapping [RingWriter::free] to `window`, [RingWriter::window] to
+    /// `get_unallocated(0, usize::MAX)` and [RingWriter::commit] to `enqueue_unallocated`. Frames
+    /// flagged bad by the ENC28J60 are dropped as for smoltcp. If the frame is longer than the
+    /// free space of the ring, [Error::FrameTooLong] is returned and the frame is left waiting, so
+    /// it can be received once the ring has drained. The software receive filter set through
+    /// [SmolEnc28j60::set_rx_filter] isn't applied, as the frame isn't held in one piece.
+    pub fn receive_to_ring(&mut self, ring: &mut impl RingWriter) -> Result<usize> {
+        self.device.receive_to_ring(ring)
+    }
+
+    /// Receive every frame waiting in the ENC28J60 buffer, calling `f` with each of them
+    ///
+    /// Returns the number of frames passed to `f`. Only the frames waiting when this is called are
+    /// received, so continuous traffic can't keep it looping. Frames flagged bad by the ENC28J60
+    /// are dropped as for smoltcp, see [SmolEnc28j60::last_rx_status]. A saturated count of 255
+    /// isn't trusted, frames are then received up to the write pointer (ERXWRPT) as it was when
+    /// this was called.
+    pub fn drain<F>(&mut self, mut f: F) -> Result<usize>
+    where
+        F: FnMut(&[u8]),
+    {
+        let (pending, end) = {
+            let mut device = self.device.device()?;
+            let pending = device.read(Register::EPKTCNT)?;
+            // EPKTCNT saturates, the frames waiting are then those before ERXWRPT
+            let end = match pending {
+                u8::MAX => Some(device.read_pointer(Pointer::ERXWRPT)?),
+                _ => None,
+            };
+            (pending, end)
+        };
+        let mut buffer = self
+            .device
+            .rx_buffer
+            .try_borrow_mut()
+            .map_err(|_| Error::Illegal)?;
+
+        let mut drained = 0;
+        let mut remaining = pending;
+        loop {
+            // Past the count, a saturated one is followed up to the write pointer frame by frame
+            let limit = if remaining > 0 {
+                remaining
+            } else if matches!(end, Some(end) if self.device.next_packet.get() != end) {
+                1
+            } else {
+                break;
+            };
+
+            // The software receive filter may read several frames for the one received
+            let mut read = 0;
+            let result = self.device.receive_limited(&mut buffer, limit, &mut read);
+            remaining = remaining.saturating_sub(read);
+            let len = match result {
+                Ok(len) => len,
+                Err(Error::InvalidFrame | Error::Filtered) => continue,
+                Err(Error::NoFrame) => break,
+                Err(error) => return Err(error),
+            };
+            if self.device.zero_on_receive {
+                buffer[len..].fill(0);
+            }
+            f(&buffer[..len]);
+            drained += 1;
+        }
+
+        Ok(drained)
+    }
+
+    /// Receive every frame waiting in the ENC28J60 buffer, returning whether one of them is a
+    /// wake-on-LAN magic packet for this device
+    ///
+    /// The ENC28J60 has no wake-on-LAN hardware, so this is a software trigger for low-power
+    /// devices. A magic packet holds 6 bytes of 0xFF followed by 16 repeats of the MAC address, and
+    /// is matched anywhere in the frame, whether sent as EtherType 0x0842 or in a UDP datagram. The
+    /// frames are consumed as by [SmolEnc28j60::drain], so they don't reach smoltcp.
+    pub fn check_magic_packet(&mut self) -> Result<bool> {
+        let mac = self.mac_address();
+        let mut found = false;
+        self.drain(|frame| found |= is_magic_packet(frame, &mac))?;
+
+        Ok(found)
+    }
+
+    /// Read a control register, or `None` if the read fails or the device is in use by a token
+    ///
+    /// This bypasses the wrapper for registers it doesn't cover, such as to apply a new errata
+    /// workaround. The bank is selected as needed.
+    #[cfg(feature = "unstable-registers")]
+    pub fn read_reg(&self, reg: Register) -> Option<u8> {
+        self.device.device().ok()?.read(reg).ok()
+    }
+
+    /// Write a control register, [Error::Illegal] is returned if the device is in use by a token
+    ///
+    /// The wrapper isn't aware of the write, state it caches such as the receive filters and buffer
+    /// layout is not updated and a later [SmolEnc28j60::reset] reprograms it.
+    #[cfg(feature = "unstable-registers")]
+    pub fn write_reg(&mut self, reg: Register, val: u8) -> Result<()> {
+        let mut device = self.device.device()?;
+        // The write may switch banks (ECON1.BSEL)
+        let result = device.write(reg, val);
+        device.forget_bank();
+        result
+    }
+
+    /// Transmit `buf` as a raw Ethernet frame, without going through smoltcp
+    ///
+    /// The ENC28J60 appends the CRC, so `buf` must not be longer than `1518 - 4` bytes or
+    /// [Error::FrameTooLong] is returned. [Error::Illegal] is returned if a transmit through a token
+    /// is in progress.
+    pub fn write_frame(&mut self, buf: &[u8]) -> Result<()> {
+        if buf.len() > BUFFER_SIZE {
+            return Err(Error::FrameTooLong);
+        }
+
+        let _tx_buffer = self
+            .device
+            .tx_buffer
+            .try_borrow_mut()
+            .map_err(|_| Error::Illegal)?;
+        self.device.send(buf)
+    }
+
+    /// Transmit the concatenation of `segments` as a raw Ethernet frame, without going through
+    /// smoltcp
+    ///
+    /// Each segment is written straight into the ENC28J60 in order, so a header and payload held in
+    /// separate buffers don't need copying together first. As for [SmolEnc28j60::write_frame] the
+    /// frame is capped at `1518 - 4` bytes, [Error::FrameTooLong] is returned before anything is
+    /// written if the segments add up to more, and [Error::Illegal] is returned if a transmit
+    /// through a token is in progress.
+    pub fn transmit_chained(&mut self, segments: &[&[u8]]) -> Result<()> {
+        let len: usize = segments.iter().map(|segment| segment.len()).sum();
+        if len > BUFFER_SIZE {
+            return Err(Error::FrameTooLong);
+        }
+
+        let _tx_buffer = self
+            .device
+            .tx_buffer
+            .try_borrow_mut()
+            .map_err(|_| Error::Illegal)?;
+        self.device.send_chained(segments)
+    }
+
+    /// Transmit a raw Ethernet frame that `f` writes straight into the ENC28J60 over SPI
+    ///
+    /// This avoids copying the frame through the TX frame buffer first, for throughput sensitive
+    /// applications. `f` returns the length of the frame, which must not exceed what it wrote
+    /// through the [EncWriter] or [Error::Illegal] is returned instead of transmitting stale buffer
+    /// memory. As for [SmolEnc28j60::write_frame] the frame is capped at `1518 - 4` bytes and
+    /// [Error::Illegal] is returned if a transmit through a token is in progress. An SPI error
+    /// while writing is returned once `f` returns.
+    pub fn transmit_with<F>(&mut self, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut EncWriter<'_, Spi, Ncs, Int, Reset>) -> usize,
+    {
+        let _tx_buffer = self
+            .device
+            .tx_buffer
+            .try_borrow_mut()
+            .map_err(|_| Error::Illegal)?;
+        self.device.send_with(|device| {
+            let mut writer = EncWriter {
+                device,
+                written: 0,
+                error: None,
+            };
+            let len = f(&mut writer);
+            match writer.error {
+                Some(error) => Err(error),
+                None if len > writer.written => Err(Error::Illegal),
+                None => Ok(len),
+            }
+        })
+    }
+
+    /// Load a frame into the buffer memory of the ENC28J60 once, so it can be transmitted any
+    /// number of times through [SmolEnc28j60::retransmit] without copying it over SPI again
+    ///
+    /// This is meant for frames sent periodically, such as beacons or heartbeats. Frames are
+    /// stored one after another in the memory following the TX buffer, each taking a control byte,
+    /// the frame and a transmit status vector (`buf.len() + 8` bytes). With the buffer layout set
+    /// up by [SmolEnc28j60::init] that leaves 526 bytes, and none if the TX buffer ends at the end of
+    /// the buffer memory, see [SmolEnc28j60::set_tx_start] to make room. [Error::InvalidLayout] is
+    /// returned if the frame doesn't fit in what is left, or overlaps the RX buffer, and
+    /// [Error::FrameTooLong] if it is longer than `1518 - 4` bytes. The memory is only given back
+    /// when the buffer layout is changed, which invalidates every handle.
+    pub fn prepare_frame(&mut self, buf: &[u8]) -> Result<FrameHandle> {
+        self.device.prepare_frame(buf)
+    }
+
+    /// Transmit a frame loaded through [SmolEnc28j60::prepare_frame], waiting for it to leave as
+    /// for [SmolEnc28j60::write_frame]
+    ///
+    /// Only the transmit pointers are written over SPI. [Error::Illegal] is returned if the buffer
+    /// layout was changed since the frame was loaded, or if a transmit through a token is in
+    /// progress.
+    pub fn retransmit(&mut self, handle: &FrameHandle) -> Result<()> {
+        let _tx_buffer = self
+            .device
+            .tx_buffer
+            .try_borrow_mut()
+            .map_err(|_| Error::Illegal)?;
+        self.device.retransmit(handle)
+    }
+
+    /// Block until the ENC28J60 has finished transmitting (ECON1.TXRTS clears)
+    ///
+    /// Transmits through a token, [SmolEnc28j60::write_frame] and [SmolEnc28j60::transmit_with]
+    /// already wait for the frame to leave before returning, so this is an explicit barrier for
+    /// stop-and-wait protocols that returns straight away when nothing is being transmitted. The
+    /// wait is bounded by [SmolEnc28j60::set_transmit_timeout], after which the transmission is
+    /// aborted and [Error::TransmitTimeout] returned.
+    pub fn flush(&mut self) -> Result<()> {
+        self.device.flush()
+    }
+
+    /// Whether a frame can be transmitted straight away
+    ///
+    /// `false` means a token is transmitting, or the previous frame is still being clocked out on
+    /// the wire (ECON1.TXRTS). This is cheaper than asking smoltcp for a token that then fails with
+    /// [smoltcp::Error::Exhausted].
+    pub fn tx_ready(&self) -> bool {
+        !self.device.transmit_busy()
+    }
+
+    /// The most recent error encountered by a token
+    ///
+    /// smoltcp only sees [smoltcp::Error::Illegal] when a transfer fails, this gives the cause. See
+    /// [Error::is_bus_error] to tell a failing SPI bus from an error reported by the ENC28J60.
+    pub fn last_error(&self) -> Option<Error> {
+        self.device.last_error.get()
+    }
+
+    /// Status of the most recently transmitted frame, as reported by the ENC28J60
+    ///
+    /// `None` until a frame has been transmitted, or if the last transmission timed out.
+    pub fn last_tx_status(&self) -> Option<TxStatus> {
+        self.device.last_tx_status.get()
+    }
+
+    /// Status of the most recently received frame, as reported by the ENC28J60
+    ///
+    /// Frames whose status isn't [RxStatus::received_ok] are dropped rather than handed to
+    /// smoltcp, and counted in [Stats::rx_dropped]. `None` until a frame has been received.
+    pub fn last_rx_status(&self) -> Option<RxStatus> {
+        self.device.last_rx_status.get()
+    }
+
+    /// Whether the most recently received frame was sent to the broadcast address
+    ///
+    /// This comes from the status the ENC28J60 reports, see [RxStatus::broadcast], so policies such
+    /// as rate limiting ARP don't need to parse the destination address. `false` until a frame has
+    /// been received.
+    pub fn last_rx_was_broadcast(&self) -> bool {
+        matches!(self.last_rx_status(), Some(status) if status.broadcast)
+    }
+
+    /// Whether the most recently received frame was sent to a multicast address, see
+    /// [SmolEnc28j60::last_rx_was_broadcast]
+    pub fn last_rx_was_multicast(&self) -> bool {
+        matches!(self.last_rx_status(), Some(status) if status.multicast)
+    }
+
+    /// Timestamp smoltcp passed with the most recently received frame
+    ///
+    /// Together with [SmolEnc28j60::last_tx_instant] this gives inter-frame gaps and round trip
+    /// times at the timestamp resolution of the application. `None` until a frame has been received
+    /// through a token.
+    pub fn last_rx_instant(&self) -> Option<Instant> {
+        self.device.last_rx_instant.get()
+    }
+
+    /// Timestamp smoltcp passed with the most recently transmitted frame, `None` until a frame has
+    /// been transmitted through a token
+    pub fn last_tx_instant(&self) -> Option<Instant> {
+        self.device.last_tx_instant.get()
+    }
+
+    /// Packet and byte counters since construction or the last [SmolEnc28j60::reset_stats]
+    pub fn stats(&self) -> Stats {
+        self.device.stats.get()
+    }
+
+    /// Tokens consumed from within the closure of another token that found their frame buffer in
+    /// use, since construction or the last [SmolEnc28j60::reset_stats]
+    ///
+    /// These are also counted in [Stats::buffer_busy] and fail with [Error::BufferBusy], which
+    /// smoltcp treats as the device being exhausted, so a closure that polls the interface, such as
+    /// through a logging callback, silently loses frames. Only counted in debug builds.
+    #[cfg(debug_assertions)]
+    pub fn reentrant_consumes(&self) -> u32 {
+        self.device.reentries.get()
+    }
+
+    /// SPI buffer memory transfers since construction or the last [SmolEnc28j60::reset_stats]
+    ///
+    /// Together with the timestamps of [SmolEnc28j60::last_rx_instant] and
+    /// [SmolEnc28j60::last_tx_instant] this shows whether the SPI clock limits throughput. If a
+    /// token is currently using the device then the counts from the last successful read are
+    /// returned instead.
+    #[cfg(feature = "metrics")]
+    pub fn spi_stats(&self) -> SpiStats {
+        if let Ok(device) = self.device.device() {
+            self.device.spi_stats.set(device.spi_stats());
+        }
+
+        self.device.spi_stats.get()
+    }
+
+    /// Reset all [Stats] counters to zero, along with the `SpiStats` counters with the `metrics`
+    /// feature
+    pub fn reset_stats(&mut self) {
+        self.device.stats.set(Stats::default());
+        #[cfg(debug_assertions)]
+        self.device.reentries.set(0);
+        #[cfg(feature = "metrics")]
+        {
+            if let Some(device) = self.device.device.get_mut() {
+                device.reset_spi_stats();
+            }
+            self.device.spi_stats.set(SpiStats::default());
+        }
+    }
+
+    /// Run `f` with the ENC28J60 driver, for operations not exposed through smoltcp
+    ///
+    /// Returns `None` without calling `f` if a token is currently using the device. `f` must not
+    /// receive frames through the driver: the wrapper reads frames itself and tracks where the next
+    /// one starts, so the receive pointer of the driver is stale once a frame has been received
+    /// and the two would read frames out of order.
+    pub fn with_device<R>(
+        &self,
+        f: impl FnOnce(&mut Enc28j60<Spi, Ncs, Int, Reset>) -> R,
+    ) -> Option<R> {
+        self.device.device().ok().map(|mut device| {
+            let result = f(&mut device);
+            device.forget_bank();
+            result
+        })
+    }
+
+    /// Split into an RX half and a TX half that can be handed to different parts of the firmware,
+    /// such as an interrupt handler receiving frames and the main loop transmitting
+    ///
+    /// Both halves share the ENC28J60 and its SPI bus through the locking tokens already do: a
+    /// half that finds the device or its frame buffer in use gets no token, or a token that fails
+    /// with [Error::BufferBusy] or [Error::Illegal], rather than interleaving SPI transfers. The
+    /// halves are not `Send` or `Sync`, so using them from an interrupt handler still needs
+    /// something like a critical section mutex around them, and the SPI bus must not be used by
+    /// anything else while either half is.
+    pub fn split(&mut self) -> Halves<'_, Spi, Ncs, Int, Reset, N> {
+        (RxHalf::new(self), TxHalf::new(self))
+    }
+
+    /// Unwrap the ENC28J60 driver, for operations not exposed through smoltcp
+    ///
+    /// The ENC28J60 is left running, so it can be wrapped again afterwards. Any frames received in
+    /// the meantime are picked up once it is. Frames must not be received through the driver
+    /// itself: the wrapper reads frames without updating the receive pointer of the driver, which
+    /// is stale once a frame has been received through the wrapper.
+    pub fn into_inner(mut self) -> Enc28j60<Spi, Ncs, Int, Reset> {
+        // Taken rather than moved out, so that Drop finds no device to quiesce
+        match self.device.device.get_mut().take() {
+            Some(device) => device.into_inner(),
+            None => unreachable!("the device is only taken by into_inner"),
+        }
+    }
+
+    /// Reset the ENC28J60 through its reset pin and initialise it again
+    ///
+    /// The device is brought back up with the stored MAC address, buffer layout, receive filters
+    /// (promiscuous mode included) and interrupt enables. Frames in flight or waiting in the
+    /// ENC28J60 buffer are lost, any tokens are invalidated by the reset. Without a reset pin
+    /// (`Reset` is [enc28j60::Unconnected]) nothing is reset, use
+    /// [SmolEnc28j60::reset_with_command] instead.
+    pub fn reset<D>(&mut self, delay: &mut D) -> Result<()>
+    where
+        D: DelayUs<u16>,
+    {
+        self.device.reset(delay)
+    }
+
+    /// Reset the ENC28J60 with the system reset command over SPI and initialise it again, as for
+    /// [SmolEnc28j60::reset]
+    ///
+    /// This is for boards without a GPIO for the reset line, and takes a little longer than a
+    /// reset through the pin as ESTAT.CLKRDY can't be relied on afterwards (errata #2).
+    pub fn reset_with_command<D>(&mut self, delay: &mut D) -> Result<()>
+    where
+        D: DelayUs<u16>,
+    {
+        self.device.reset_with_command(delay)
+    }
+
+    /// Write the key control, pointer and PHY status registers to `out` in a human readable block,
+    /// such as for a bug report
+    ///
+    /// [Error::Illegal] is returned if a token is in use, and [Error::Format] if writing to `out`
+    /// fails.
+    pub fn dump_registers(&self, out: &mut impl core::fmt::Write) -> Result<()> {
+        self.device.dump_registers(out)
+    }
+
+    /// Check the SPI and MAC datapath by sending a frame through PHY loopback (PHCON1.PLOOPBK)
+    ///
+    /// A known frame is transmitted with the PHY looping it back rather than putting it on the
+    /// wire, so no cable is needed. Returns `Ok(true)` if the frame is received back unchanged.
+    /// Frames already waiting in the ENC28J60 buffer are discarded. The duplex mode is switched to
+    /// full for the test, as loopback requires, and restored afterwards.
+    pub fn self_test(&mut self) -> Result<bool> {
+        self.device.self_test()
+    }
+
+    /// Put the ENC28J60 into power save mode (ECON2.PWRSV)
+    ///
+    /// Reception is disabled and any frame being received or transmitted is allowed to finish
+    /// first, [Error::Illegal] is returned if that takes too long. No tokens are handed to smoltcp
+    /// while powered down, frames arriving in the meantime are lost.
+    pub fn power_down(&mut self) -> Result<()> {
+        self.device.power_down()
+    }
+
+    /// Bring the ENC28J60 out of power save mode and enable reception again
+    ///
+    /// Waking takes around 300 µs for the PHY to stabilise, which is waited for by polling
+    /// ESTAT.CLKRDY.
+    pub fn power_up(&mut self) -> Result<()> {
+        self.device.power_up()
+    }
+
+    /// Pause or resume reception (ECON1.RXEN), enabled by default
+    ///
+    /// This is for firmware that must stop receiving for a while, such as during a flash write
+    /// that can't tolerate interrupts, without tearing down the interface. No tokens for received
+    /// frames are handed to smoltcp while reception is disabled, though frames already waiting in
+    /// the ENC28J60 buffer are kept for when it is enabled again. Frames arriving in the meantime
+    /// are lost, the ENC28J60 doesn't buffer them. A frame already being received is still
+    /// completed. The setting is kept across [SmolEnc28j60::reset] and
+    /// [SmolEnc28j60::power_up].
+    pub fn set_rx_enabled(&mut self, enabled: bool) -> Result<()> {
+        self.device.set_rx_enabled(enabled)
+    }
+
+    /// Enable or disable promiscuous mode
+    ///
+    /// In promiscuous mode the ENC28J60 receive filters are disabled, so every frame with a valid
+    /// CRC is received rather than only unicast frames for our MAC address and broadcast frames.
+    /// Takes effect from the next frame received.
+    pub fn set_promiscuous(&mut self, enabled: bool) -> Result<()> {
+        let filter = RxFilter {
+            promiscuous: enabled,
+            ..self.device.filter.get()
+        };
+        self.device.set_filter(filter)
+    }
+
+    /// Accept or reject all multicast frames
+    ///
+    /// smoltcp needs multicast frames for IPv6 and mDNS. Accepting every multicast frame is simple
+    /// but on a busy network means smoltcp parses and discards a lot of traffic, see
+    /// [SmolEnc28j60::multicast_hash] to only accept the groups of interest.
+    pub fn set_multicast(&mut self, accept: bool) -> Result<()> {
+        let filter = RxFilter {
+            multicast: accept,
+            ..self.device.filter.get()
+        };
+        self.device.set_filter(filter)
+    }
+
+    /// Accept or reject broadcast frames, accepted by default
+    ///
+    /// **Rejecting broadcast frames breaks ARP and DHCP**, smoltcp can then only reach neighbours
+    /// whose addresses are configured statically. This is meant for point-to-point links that
+    /// must shrug off broadcast storms in hardware. Promiscuous mode still receives broadcast
+    /// frames, see [SmolEnc28j60::set_promiscuous].
+    pub fn set_accept_broadcast(&mut self, accept: bool) -> Result<()> {
+        let filter = RxFilter {
+            reject_broadcast: !accept,
+            ..self.device.filter.get()
+        };
+        self.device.set_filter(filter)
+    }
+
+    /// Program the hash table filter with the given destination addresses
+    ///
+    /// Frames whose destination address hashes into the same one of the 64 hash table entries are
+    /// also accepted, so smoltcp must still discard the occasional unwanted frame. Accepting all
+    /// multicast frames through [SmolEnc28j60::set_multicast] takes precedence over the hash table.
+    /// An empty list of addresses disables the hash table filter.
+    pub fn multicast_hash(&mut self, entries: &[[u8; 6]]) -> Result<()> {
+        let filter = RxFilter {
+            hash_table: hash_table(entries),
+            ..self.device.filter.get()
+        };
+        self.device.set_filter(filter)
+    }
+
+    /// Set the checksum capabilities reported to smoltcp
+    ///
+    /// The ENC28J60 has no checksum offload, so by default smoltcp computes and verifies every
+    /// checksum in software. Only override this if checksums are handled elsewhere.
+    pub fn set_checksum_caps(&mut self, caps: ChecksumCapabilities) {
+        self.checksum = caps;
+    }
+
+    /// Set the duplex mode of the MAC (MACON3.FULDPX) and PHY (PHCON1.PDPXMD)
+    ///
+    /// This must match the link partner, a mismatch shows up as collisions and CRC errors. The
+    /// ENC28J60 can't autonegotiate, so set full duplex only when the partner is fixed to it too.
+    /// Changing the duplex mode while a frame is being transmitted is undefined, so
+    /// [Error::Illegal] is returned instead. The mode is kept across [SmolEnc28j60::reset].
+    pub fn set_duplex(&mut self, full: bool) -> Result<()> {
+        self.device.set_duplex(full)
+    }
+
+    /// Zero the unused tail of the RX buffer after each frame is read, disabled by default
+    ///
+    /// smoltcp is only handed the received frame, but the rest of the buffer still holds whatever
+    /// longer frame was received before it. Enable this in deployments where stale frame data must
+    /// not linger in memory, at the cost of clearing the rest of the buffer for every frame.
+    pub fn set_zero_on_receive(&mut self, enabled: bool) {
+        self.device.zero_on_receive = enabled;
+    }
+
+    /// Drop received frames with a bad IPv4, UDP or TCP checksum before they reach smoltcp,
+    /// disabled by default
+    ///
+    /// With the `verify-checksums` feature the checksums of every frame received through a token
+    /// are verified in software and failures counted in [Stats::rx_checksum_errors], which shows
+    /// corruption on a flaky link that smoltcp would otherwise drop silently. A dropped frame is
+    /// also counted in [Stats::rx_dropped] and fails with [Error::InvalidFrame]. Verifying costs a
+    /// pass over the IP header and the TCP or UDP segment, about as much as smoltcp spends checking
+    /// it again, so turn the checks off in smoltcp through [SmolEnc28j60::set_checksum_caps] once
+    /// frames are dropped here.
+    #[cfg(feature = "verify-checksums")]
+    pub fn set_drop_bad_checksums(&mut self, enabled: bool) {
+        self.device.drop_bad_checksums = enabled;
+    }
+
+    /// Set what the LEDs driven by the ENC28J60 show (PHLCON)
+    ///
+    /// By default LEDA shows the link status and LEDB receive activity. Events are stretched so
+    /// they stay visible for at least 40 ms. The modes are kept across [SmolEnc28j60::reset].
+    pub fn set_leds(&mut self, leda: LedMode, ledb: LedMode) -> Result<()> {
+        self.device.set_leds([leda, ledb])
+    }
+
+    /// Set whether the ENC28J60 sends 802.3x pause frames to hold off the link partner, disabled by
+    /// default
+    ///
+    /// Pause frames only work in full duplex, see [SmolEnc28j60::set_duplex], and only help if the
+    /// link partner honours them, as most switches can be configured to. [Error::Illegal] is
+    /// returned when enabling flow control in half duplex, where the ENC28J60 would jam the link
+    /// instead. The mode is kept across [SmolEnc28j60::reset].
+    pub fn set_flow_control(&mut self, mode: FlowControl) -> Result<()> {
+        self.device.set_flow_control(mode)
+    }
+
+    /// Drop received frames for which `f` returns `false` before they reach smoltcp, or accept
+    /// every frame with `None`
+    ///
+    /// `f` is called with each received frame, such as to only accept some EtherTypes or
+    /// destination addresses. This is a coarse complement to the hardware filters of
+    /// [SmolEnc28j60::set_promiscuous], [SmolEnc28j60::set_multicast] and
+    /// [SmolEnc28j60::multicast_hash]: the frame has already been read over SPI, but smoltcp is
+    /// spared parsing it. The next waiting frame is read in place of a dropped one, and dropped
+    /// frames are counted in [Stats::rx_filtered]. A token whose frames are all dropped fails with
+    /// [Error::Filtered].
+    pub fn set_rx_filter(&mut self, f: Option<fn(&[u8]) -> bool>) {
+        self.device.rx_filter = f;
+    }
+
+    /// Call `hook` between polls while waiting for a frame to be transmitted, or nothing with
+    /// `None` as by default
+    ///
+    /// Transmitting blocks until ECON1.TXRTS clears, which on a cooperative scheduler starves other
+    /// tasks for as long as the frame takes on the wire. The hook lets the scheduler run other work
+    /// in the meantime without the `async` feature. It is called with the device in use, so it
+    /// must not use the device or any tokens, which would fail.
+    pub fn set_yield_hook(&mut self, hook: Option<fn()>) {
+        self.device.yield_hook = hook;
+    }
+
+    /// Tag transmitted frames with the given 802.1Q VLAN ID, or stop tagging them with `None`
+    ///
+    /// The ENC28J60 has no VLAN support, so the tag is inserted in software after the source
+    /// address of each frame transmitted through a token, with a priority of 0. Only the low 12 bits
+    /// of `vid` are used. The tag takes 4 bytes of the frame buffer, so the MTU reported to smoltcp
+    /// shrinks by 4 bytes while tagging. Received frames are passed to smoltcp unchanged and frames
+    /// sent through [SmolEnc28j60::write_frame] are not tagged.
+    pub fn set_vlan(&mut self, vid: Option<u16>) {
+        debug_assert!(
+            vid.is_none() || self.medium != phy::Medium::Ip,
+            "VLAN tags can't be inserted into IP packets"
+        );
+        self.device.vlan = vid.map(|vid| vid & 0x0FFF);
+    }
+
+    /// Set the number of ECON1 polls to wait for a transmission to finish, defaults to 100 000
+    ///
+    /// A transmission still in progress after this many polls is aborted and
+    /// [Error::TransmitTimeout] is recorded, rather than hanging on a wedged ENC28J60. How long this
+    /// takes depends on the SPI clock, a poll takes a few microseconds at typical clocks.
+    pub fn set_transmit_timeout(&mut self, polls: u32) {
+        self.device.transmit_polls = polls;
+    }
+
+    /// Split frame transfers to and from the ENC28J60 buffer memory into SPI transfers of at most
+    /// `bytes` bytes, 0 to use a single transfer per frame as by default
+    ///
+    /// Some SPI peripherals and DMA setups cope badly with one long transfer. Smaller chunks let
+    /// them keep up and bound how long the bus is held by one transfer, at the cost of throughput
+    /// as every chunk adds a command byte and a chip select toggle. Frames written through
+    /// [SmolEnc28j60::transmit_with] are transferred as written.
+    pub fn set_spi_chunk(&mut self, bytes: usize) {
+        self.device.spi_chunk = bytes;
+    }
+
+    /// Retry sending or receiving a frame up to `retries` times when an SPI transfer fails, 0 to
+    /// fail straight away as by default
+    ///
+    /// This rides out brief glitches on a noisy bus, such as a single corrupted transfer, that
+    /// would otherwise lose the frame. Only bus errors are retried, see [Error::is_bus_error], and
+    /// each retry is counted in [Stats::spi_retries] so a failing bus doesn't go unnoticed. Frames
+    /// written through [SmolEnc28j60::transmit_with] can't be written again and aren't retried.
+    pub fn set_spi_retries(&mut self, retries: u8) {
+        self.device.spi_retries = retries;
+    }
+
+    /// Override the inter-packet gaps (MABBIPG, MAIPGL and MAIPGH)
+    ///
+    /// This is an advanced setting for non-standard links, such as long cables or media converters,
+    /// where the recommended gaps cause excess collisions. The recommended gaps are `0x15`, `0x12`
+    /// and unused for full duplex, and `0x12`, `0x12` and `0x0C` for half duplex, see the
+    /// datasheet for how the values translate to bit times. The override is kept across
+    /// [SmolEnc28j60::set_duplex] and [SmolEnc28j60::reset].
+    pub fn set_ipg(&mut self, bbipg: u8, nbipg_l: u8, nbipg_h: u8) -> Result<()> {
+        self.device.set_ipg(InterPacketGaps {
+            back_to_back: bbipg,
+            non_back_to_back_low: nbipg_l,
+            non_back_to_back_high: nbipg_h,
+        })
+    }
+
+    /// Move the RX buffer of the ENC28J60 to `rx_start..=rx_end` in its 8 KiB buffer memory
+    ///
+    /// The rest of the memory is free for other uses, but the TX buffer at ETXST must keep room for
+    /// a control byte, a full frame and its transmit status vector (1522 bytes), see
+    /// [SmolEnc28j60::set_tx_start]. [Error::InvalidLayout] is returned if the RX buffer overlaps
+    /// it, and also unless `rx_start` is even and `rx_end` odd: the ENC28J60 doesn't free the byte
+    /// at ERXRDPT, which must be odd (errata #14), so the driver points it just behind each frame
+    /// read and at `rx_end` when the next frame is at `rx_start`. An `rx_start` other than 0x0000
+    /// goes against errata #5 and is warned about through the logging features, see
+    /// [SmolEnc28j60::set_rx_buffer_size].
+    ///
+    /// Reception is disabled while the boundaries are reprogrammed, frames waiting in the RX buffer
+    /// are discarded. The layout is kept across [SmolEnc28j60::reset].
+    pub fn set_buffer_split(&mut self, rx_start: u16, rx_end: u16) -> Result<()> {
+        let layout = Layout {
+            rx_start,
+            rx_end,
+            ..self.device.layout.get()
+        };
+        self.device.set_layout(layout)
+    }
+
+    /// Move the TX buffer of the ENC28J60 to start at `addr` (ETXST) in its 8 KiB buffer memory
+    ///
+    /// The TX buffer takes 1522 bytes from `addr`, for the control byte, a full frame and its
+    /// transmit status vector. A TX buffer overlapping the RX buffer would overwrite received
+    /// frames with each transmission, so [Error::InvalidLayout] is returned if it would, or if it
+    /// runs past the end of the buffer memory. Move the RX buffer out of the way first with
+    /// [SmolEnc28j60::set_buffer_split] to swap them around.
+    ///
+    /// As for [SmolEnc28j60::set_buffer_split], frames waiting in the RX buffer are discarded and
+    /// the layout is kept across [SmolEnc28j60::reset].
+    pub fn set_tx_start(&mut self, addr: u16) -> Result<()> {
+        let layout = Layout {
+            tx_start: addr,
+            ..self.device.layout.get()
+        };
+        self.device.set_layout(layout)
+    }
+
+    /// Give the first `rx_size` bytes of the buffer memory to the RX buffer and the rest to the TX
+    /// buffer, following errata #5
+    ///
+    /// Errata #5: the receive hardware can corrupt the RX buffer unless it starts at 0x0000, which
+    /// this enforces. [SmolEnc28j60::set_buffer_split] allows any start, while the constructors move
+    /// an RX buffer found elsewhere to 0x0000, keeping its size. [Error::InvalidLayout] is
+    /// returned unless `rx_size` is even and leaves room for the TX buffer, see
+    /// [SmolEnc28j60::set_buffer_split], which this otherwise behaves as.
+    pub fn set_rx_buffer_size(&mut self, rx_size: u16) -> Result<()> {
+        self.device.set_layout(Layout::with_rx_size(rx_size))
+    }
+
+    /// Discard every frame in the RX buffer and restart reception, as a lighter recovery than
+    /// [SmolEnc28j60::reset]
+    ///
+    /// Reception is disabled and the receive logic reset (ECON1.RXRST), then the read pointers are
+    /// moved back to the start of the RX buffer with ERXRDPT at its odd end (errata #14), the
+    /// overflow flag (EIR.RXERIF) cleared and reception enabled again. This recovers from a
+    /// receive buffer overflow that left the pointers inconsistent. The discarded frames are
+    /// counted in [Stats::rx_dropped]. [Error::Illegal] is returned while the ENC28J60 is powered
+    /// down or in use by a token.
+    pub fn flush_rx(&mut self) -> Result<()> {
+        self.device.flush_rx()
+    }
+
+    /// Buffer received frames in `ring` ahead of smoltcp
+    ///
+    /// Every poll for a token moves the frames waiting in the ENC28J60 into the ring, up to its
+    /// depth `R`, and RX tokens hand smoltcp the oldest frame in the ring. This frees the ENC28J60
+    /// buffer during bursts that smoltcp can't keep pace with, at the cost of `R` frames of
+    /// `1518 - 4` bytes and a length each on top of the frame buffers. Frames taken into the ring
+    /// are no longer seen by [SmolEnc28j60::read_frame] and [SmolEnc28j60::drain].
+    #[cfg(feature = "heapless")]
+    pub fn set_rx_ring<const R: usize>(&mut self, ring: &'static mut heapless::Deque<Frame, R>) {
+        *self.device.ring.get_mut() = Some(ring);
+    }
+
+    /// Receive into buffers taken from `pool` rather than the RX frame buffer
+    ///
+    /// Each RX token takes a buffer from the pool when it is handed out and returns it once it is
+    /// consumed or dropped, so up to `P` RX tokens can be held at once to smooth out bursty
+    /// traffic, see [SmolEnc28j60::set_max_burst_size]. No RX tokens are handed out while the pool
+    /// is empty. The pool costs `P` buffers of `1518 - 4` bytes each for full frames, shorter
+    /// buffers truncate the frames received into them.
+    #[cfg(feature = "heapless")]
+    pub fn set_rx_pool<const P: usize>(
+        &mut self,
+        pool: &'static mut heapless::Vec<&'static mut [u8], P>,
+    ) {
+        *self.device.pool.get_mut() = Some(pool);
+    }
+
+    /// Set the maximum burst size reported to smoltcp
+    ///
+    /// smoltcp limits the TCP window to this many segments, so that a burst of frames from the peer
+    /// doesn't overflow the RX buffer of the ENC28J60 before they are read. Defaults to the number
+    /// of full frames fitting in the RX buffer, 4 with the default 6 KB, which follows changes to
+    /// the buffer split. Frames are read one at a time whatever the burst size, a token fails with
+    /// [smoltcp::Error::Exhausted] rather than waiting for another one to finish.
+    pub fn set_max_burst_size(&mut self, n: Option<usize>) {
+        self.max_burst_size = Some(n);
+    }
+
+    /// Burst size reported to smoltcp, see [SmolEnc28j60::set_max_burst_size]
+    fn max_burst_size(&self) -> Option<usize> {
+        self.max_burst_size.unwrap_or_else(|| {
+            let rx_size = usize::from(self.device.layout.get().rx_size());
+            Some((rx_size / RX_FRAME_SIZE).max(1))
+        })
+    }
+
+    /// Set the MTU reported to smoltcp, defaults to and is clamped to the smaller of the frame
+    /// buffer size and `1518 - 4`
+    pub fn set_mtu(&mut self, mtu: usize) {
+        self.mtu = mtu.min(self.device.capacity).min(BUFFER_SIZE);
+    }
+
+    /// Set the medium reported to smoltcp, defaults to [phy::Medium::Ethernet]
+    ///
+    /// The ENC28J60 only speaks Ethernet, [phy::Medium::Ip] is for setups tunnelling IP packets
+    /// inside the frames, which the wrapper passes through unchanged. As the wrapper can't tag IP
+    /// packets, combining [phy::Medium::Ip] with [SmolEnc28j60::set_vlan] and choosing
+    /// [phy::Medium::Ieee802154] are caught by debug assertions.
+    pub fn set_medium(&mut self, medium: phy::Medium) {
+        debug_assert!(
+            medium != phy::Medium::Ieee802154,
+            "the ENC28J60 can't carry IEEE 802.15.4 frames"
+        );
+        debug_assert!(
+            medium != phy::Medium::Ip || self.device.vlan.is_none(),
+            "VLAN tags can't be inserted into IP packets"
+        );
+        self.medium = medium;
+    }
+
+    /// Tokens for a received frame, only needing a shared reference as the tokens lock what they
+    /// use themselves
+    fn rx_tokens(&self) -> Option<Tokens<'_, Spi, Ncs, Int, Reset, N>> {
+        Some((
+            self.rx_token()?,
+            TxToken {
+                lower: &self.device,
+            },
+        ))
+    }
+
+    /// Token for a received frame, without a TX token to reply through
+    fn rx_token(&self) -> Option<RxToken<'_, Spi, Ncs, Int, Reset, N>> {
+        if self.device.powered_down.get() || !self.device.rx_enabled.get() {
+            return None;
+        }
+
+        #[cfg(feature = "heapless")]
+        self.device.fill_ring();
+        if !self.device.rx_pending() {
+            return None;
+        }
+
+        // Taken last, so it isn't lost to an early return
+        #[cfg(feature = "heapless")]
+        let buffer = match self.device.pool.try_borrow_mut().ok()?.as_mut() {
+            Some(pool) => Some(pool.take()?),
+            None => None,
+        };
+
+        Some(RxToken {
+            lower: &self.device,
+            #[cfg(feature = "heapless")]
+            buffer: Cell::new(buffer),
+        })
+    }
+
+    /// Token to transmit a frame, see [SmolEnc28j60::rx_tokens]
+    fn tx_token(&self) -> Option<TxToken<'_, Spi, Ncs, Int, Reset, N>> {
+        if self.device.powered_down.get() || self.device.transmit_busy() {
+            return None;
+        }
+
+        Some(TxToken {
+            lower: &self.device,
+        })
+    }
+}
+
+impl<Spi, Ncs, Int, Reset> SmolEnc28j60<Spi, Ncs, Int, Reset, 0>
+where
+    Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8>,
+    Ncs: OutputPin,
+    Int: enc28j60::IntPin,
+    Reset: enc28j60::ResetPin,
+{
+    /// Wrap an ENC28J60 using `buf` for the frame buffers rather than embedding them
+    ///
+    /// This lets `buf` be placed in a memory region, or with an alignment, that DMA-backed SPI
+    /// requires. `buf` is split in half between RX and TX, so it needs to be `2 * (1518 - 4)` bytes
+    /// for full frames. The wrapper embeds no buffer of its own, its `N` is 0. As for the embedded
+    /// buffers both halves start word-aligned: up to 3 bytes are skipped at the start of `buf`,
+    /// and it is split at a multiple of 4 bytes, so align `buf` to 4 bytes to use all of it.
+    pub fn with_buffer(enc: Enc28j60<Spi, Ncs, Int, Reset>, buf: &'static mut [u8]) -> Self {
+        let skip = buf.as_ptr().align_offset(FRAME_ALIGN).min(buf.len());
+        let buf = &mut buf[skip..];
+        let half = buf.len() / 2 / FRAME_ALIGN * FRAME_ALIGN;
+        let (rx_buffer, tx_buffer) = buf.split_at_mut(half);
+        Self::with_frame_buffers(
+            enc,
+            false,
+            FrameBuffer::External(rx_buffer),
+            FrameBuffer::External(tx_buffer),
+        )
+    }
+}
+
+impl<Spi, Ncs, Int, Reset, const N: usize> Drop for SmolEnc28j60<Spi, Ncs, Int, Reset, N>
+where
+    Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8>,
+    Ncs: OutputPin,
+    Int: enc28j60::IntPin,
+    Reset: enc28j60::ResetPin,
+{
+    /// Stop reception and mask interrupts, so the ENC28J60 is left quiet for whatever uses the SPI
+    /// bus next. Errors can't be reported from drop and are ignored, and nothing is done once the
+    /// driver was taken by [SmolEnc28j60::into_inner].
+    fn drop(&mut self) {
+        self.device.quiesce();
+    }
+}
+
+impl<Spi, Ncs, Int, Reset, const N: usize> core::fmt::Debug
+    for SmolEnc28j60<Spi, Ncs, Int, Reset, N>
+where
+    Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8>,
+    Ncs: OutputPin,
+    Int: enc28j60::IntPin,
+    Reset: enc28j60::ResetPin,
+{
+    /// Only the configuration and counters cached by the wrapper are printed, the ENC28J60 isn't
+    /// accessed, so this is safe to use while a token is held
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let filter = self.device.filter.get();
+        f.debug_struct("SmolEnc28j60")
+            .field("mac_address", &self.device.mac.get())
+            .field("mtu", &self.mtu)
+            .field("medium", &self.medium)
+            .field("max_burst_size", &self.max_burst_size())
+            .field("promiscuous", &filter.promiscuous)
+            .field("multicast", &filter.multicast)
+            .field("accept_broadcast", &!filter.reject_broadcast)
+            .field("full_duplex", &self.device.full_duplex.get())
+            .field("vlan", &self.device.vlan)
+            .field("powered_down", &self.device.powered_down.get())
+            .field("rx_enabled", &self.device.rx_enabled.get())
+            .field("stats", &self.device.stats.get())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a, Spi, Ncs, Int, Reset, const N: usize> SmolDevice<'a>
+    for SmolEnc28j60<Spi, Ncs, Int, Reset, N>
+where
+    Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8> + 'a,
+    Ncs: OutputPin + 'a,
+    Int: enc28j60::IntPin + 'a,
+    Reset: enc28j60::ResetPin + 'a,
+{
+    type RxToken = RxToken<'a, Spi, Ncs, Int, Reset, N>;
+
+    type TxToken = TxToken<'a, Spi, Ncs, Int, Reset, N>;
+
+    fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
+        self.rx_tokens()
+    }
+
+    fn transmit(&'a mut self) -> Option<Self::TxToken> {
+        self.tx_token()
+    }
+
+    fn capabilities(&self) -> smoltcp::phy::DeviceCapabilities {
+        let mut cap = DeviceCapabilities::default();
+        cap.medium = self.medium;
+        cap.max_transmission_unit = self.mtu.min(self.device.max_frame_len());
+        cap.max_burst_size = self.max_burst_size();
+        cap.checksum = self.checksum.clone();
+        cap
+    }
+}
+
+/// Builder for a fully configured [SmolEnc28j60]
+///
+/// Collects the options otherwise set one by one after construction and applies them in one go
+/// through [SmolEnc28j60Builder::build]. Use `SmolEnc28j60Builder::<N>::default()` for a frame
+/// buffer size other than the default, see [SmolEnc28j60::new].
+#[derive(Clone)]
+pub struct SmolEnc28j60Builder<const N: usize = BUFFER_SIZE> {
+    filter: RxFilter,
+    full_duplex: Option<bool>,
+    checksum: ChecksumCapabilities,
+    max_burst_size: Option<Option<usize>>,
+    mtu: usize,
+    preserve_crc: bool,
+    zero_on_receive: bool,
+    transmit_polls: u32,
+}
+
+impl SmolEnc28j60Builder {
+    /// Builder with the same defaults as [SmolEnc28j60::from]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<const N: usize> Default for SmolEnc28j60Builder<N> {
+    fn default() -> Self {
+        SmolEnc28j60Builder {
+            filter: RxFilter::default(),
+            full_duplex: None,
+            checksum: ChecksumCapabilities::default(),
+            max_burst_size: None,
+            mtu: N.min(BUFFER_SIZE),
+            preserve_crc: false,
+            zero_on_receive: false,
+            transmit_polls: TRANSMIT_POLLS,
+        }
+    }
+}
+
+impl<const N: usize> SmolEnc28j60Builder<N> {
+    /// See [SmolEnc28j60::set_promiscuous]
+    pub fn promiscuous(mut self, enabled: bool) -> Self {
+        self.filter.promiscuous = enabled;
+        self
+    }
+
+    /// See [SmolEnc28j60::set_multicast]
+    pub fn multicast(mut self, accept: bool) -> Self {
+        self.filter.multicast = accept;
+        self
+    }
+
+    /// See [SmolEnc28j60::set_accept_broadcast]
+    pub fn accept_broadcast(mut self, accept: bool) -> Self {
+        self.filter.reject_broadcast = !accept;
+        self
+    }
+
+    /// See [SmolEnc28j60::multicast_hash]
+    pub fn multicast_hash(mut self, entries: &[[u8; 6]]) -> Self {
+        self.filter.hash_table = hash_table(entries);
+        self
+    }
+
+    /// See [SmolEnc28j60::set_duplex], the duplex mode the ENC28J60 is in is kept if not set
+    pub fn duplex(mut self, full: bool) -> Self {
+        self.full_duplex = Some(full);
+        self
+    }
+
+    /// See [SmolEnc28j60::set_checksum_caps]
+    pub fn checksum_caps(mut self, caps: ChecksumCapabilities) -> Self {
+        self.checksum = caps;
+        self
+    }
+
+    /// See [SmolEnc28j60::set_max_burst_size]
+    pub fn max_burst_size(mut self, n: Option<usize>) -> Self {
+        self.max_burst_size = Some(n);
+        self
+    }
+
+    /// See [SmolEnc28j60::set_mtu]
+    pub fn mtu(mut self, mtu: usize) -> Self {
+        self.mtu = mtu;
+        self
+    }
+
+    /// See [SmolEnc28j60::new]
+    pub fn preserve_crc(mut self, preserve: bool) -> Self {
+        self.preserve_crc = preserve;
+        self
+    }
+
+    /// See [SmolEnc28j60::set_zero_on_receive]
+    pub fn zero_on_receive(mut self, enabled: bool) -> Self {
+        self.zero_on_receive = enabled;
+        self
+    }
+
+    /// See [SmolEnc28j60::set_transmit_timeout]
+    pub fn transmit_timeout(mut self, polls: u32) -> Self {
+        self.transmit_polls = polls;
+        self
+    }
+
+    /// Wrap an ENC28J60 and apply the collected options
+    ///
+    /// Reception is disabled while the receive filters and duplex mode are programmed, so no frame
+    /// is received before the configuration is complete.
+    pub fn build<Spi, Ncs, Int, Reset>(
+        self,
+        enc: Enc28j60<Spi, Ncs, Int, Reset>,
+    ) -> Result<SmolEnc28j60<Spi, Ncs, Int, Reset, N>>
+    where
+        Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8>,
+        Ncs: OutputPin,
+        Int: enc28j60::IntPin,
+        Reset: enc28j60::ResetPin,
+    {
+        let mut device = SmolEnc28j60::new(enc, self.preserve_crc);
+        let full_duplex = self
+            .full_duplex
+            .unwrap_or_else(|| device.device.full_duplex.get());
+        device.device.configure_filters(self.filter, full_duplex)?;
+        device.set_checksum_caps(self.checksum);
+        device.max_burst_size = self.max_burst_size;
+        device.set_mtu(self.mtu);
+        device.set_zero_on_receive(self.zero_on_receive);
+        device.set_transmit_timeout(self.transmit_polls);
+        Ok(device)
+    }
+}
+
+struct InnerEnc28j60<Spi, Ncs, Int, Reset, const N: usize>
+where
+    Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8>,
+    Ncs: OutputPin,
+    Int: enc28j60::IntPin,
+    Reset: enc28j60::ResetPin,
+{
+    /// Only `None` once taken by [SmolEnc28j60::into_inner]
+    device: RefCell<Option<BankedEnc28j60<Spi, Ncs, Int, Reset>>>,
+    rx_buffer: RefCell<FrameBuffer<N>>,
+    tx_buffer: RefCell<FrameBuffer<N>>,
+    /// Size of the smaller frame buffer
+    capacity: usize,
+    mac: Cell<[u8; 6]>,
+    revision: Cell<u8>,
+    link_up: Cell<bool>,
+    last_error: Cell<Option<Error>>,
+    last_tx_status: Cell<Option<TxStatus>>,
+    last_rx_status: Cell<Option<RxStatus>>,
+    last_rx_instant: Cell<Option<Instant>>,
+    last_tx_instant: Cell<Option<Instant>>,
+    #[cfg(feature = "heapless")]
+    ring: RefCell<Option<&'static mut dyn FrameRing>>,
+    #[cfg(feature = "heapless")]
+    pool: RefCell<Option<&'static mut dyn BufferPool>>,
+    filter: Cell<RxFilter>,
+    /// Interrupts enabled in EIE, which are enabled again after a reset
+    interrupts: Cell<u8>,
+    full_duplex: Cell<bool>,
+    /// Inter-packet gaps overriding the recommended ones
+    gaps: Cell<Option<InterPacketGaps>>,
+    leds: Cell<Option<[LedMode; 2]>>,
+    flow_control: Cell<FlowControl>,
+    /// Whether pause frames are being sent
+    paused: Cell<bool>,
+    powered_down: Cell<bool>,
+    /// Whether reception is enabled, see [SmolEnc28j60::set_rx_enabled]
+    rx_enabled: Cell<bool>,
+    layout: Cell<Layout>,
+    /// Address of the next frame to read, which the driver's own receive pointer isn't kept in
+    /// step with
+    next_packet: Cell<u16>,
+    stats: Cell<Stats>,
+    /// Bytes taken by frames loaded through [SmolEnc28j60::prepare_frame] after the TX buffer
+    prepared_len: Cell<u16>,
+    /// Incremented with each change of the layout, so stale [FrameHandle]s can be told apart
+    layout_generation: Cell<u16>,
+    /// Whether a token is running the closure it was consumed with
+    #[cfg(debug_assertions)]
+    consuming: Cell<bool>,
+    /// See [SmolEnc28j60::reentrant_consumes]
+    #[cfg(debug_assertions)]
+    reentries: Cell<u32>,
+    /// Last [SpiStats] read from the driver
+    #[cfg(feature = "metrics")]
+    spi_stats: Cell<SpiStats>,
+    preserve_crc: bool,
+    zero_on_receive: bool,
+    #[cfg(feature = "verify-checksums")]
+    drop_bad_checksums: bool,
+    transmit_polls: u32,
+    spi_chunk: usize,
+    spi_retries: u8,
+    vlan: Option<u16>,
+    rx_filter: Option<fn(&[u8]) -> bool>,
+    yield_hook: Option<fn()>,
+}
+
+impl<Spi, Ncs, Int, Reset, const N: usize> InnerEnc28j60<Spi, Ncs, Int, Reset, N>
+where
+    Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8>,
+    Ncs: OutputPin,
+    Int: enc28j60::IntPin,
+    Reset: enc28j60::ResetPin,
+{
+    fn new(
+        device: Enc28j60<Spi, Ncs, Int, Reset>,
+        preserve_crc: bool,
+        rx_buffer: FrameBuffer<N>,
+        tx_buffer: FrameBuffer<N>,
+    ) -> Self {
+        let mut device = BankedEnc28j60::new(device);
+        let mac = device.read_mac_address().unwrap_or_default();
+        let revision = device.read(Register::EREVID).unwrap_or(0);
+        let interrupts = device.read(Register::EIE).unwrap_or(0);
+        let link_up = device.read_link_up().unwrap_or(false);
+        let full_duplex = device
+            .read(Register::MACON3)
+            .map(|macon3| macon3 & MACON3_FULDPX != 0)
+            .unwrap_or(false);
+        let mut layout = Layout::read(&mut device).unwrap_or_default();
+        let relocate = !layout.is_errata_safe();
+        if relocate {
+            warn!(
+                "ERXST is 0x{:X}, moving the RX buffer to 0x0000 for errata #5",
+                layout.rx_start
+            );
+            layout = Some(Layout::with_rx_size(layout.rx_size()))
+                .filter(|layout| layout.is_valid())
+                .unwrap_or_default();
+        }
+        // The driver leaves ERXRDPT just behind the next frame to be read (errata #14)
+        let next_packet = match device.read_pointer(Pointer::ERXRDPT) {
+            Ok(rdpt) if rdpt != layout.rx_end => rdpt.wrapping_add(1),
+            _ => layout.rx_start,
+        };
+
+        let inner = InnerEnc28j60 {
+            device: RefCell::new(Some(device)),
+            capacity: rx_buffer.len().min(tx_buffer.len()),
+            rx_buffer: RefCell::new(rx_buffer),
+            tx_buffer: RefCell::new(tx_buffer),
+            mac: Cell::new(mac),
+            revision: Cell::new(revision),
+            link_up: Cell::new(link_up),
+            last_error: Cell::new(None),
+            last_tx_status: Cell::new(None),
+            last_rx_status: Cell::new(None),
+            last_rx_instant: Cell::new(None),
+            last_tx_instant: Cell::new(None),
+            #[cfg(feature = "heapless")]
+            ring: RefCell::new(None),
+            #[cfg(feature = "heapless")]
+            pool: RefCell::new(None),
+            filter: Cell::new(RxFilter::default()),
+            interrupts: Cell::new(interrupts),
+            full_duplex: Cell::new(full_duplex),
+            gaps: Cell::new(None),
+            leds: Cell::new(None),
+            flow_control: Cell::new(FlowControl::Off),
+            paused: Cell::new(false),
+            powered_down: Cell::new(false),
+            rx_enabled: Cell::new(true),
+            layout: Cell::new(layout),
+            next_packet: Cell::new(next_packet),
+            stats: Cell::new(Stats::default()),
+            prepared_len: Cell::new(0),
+            layout_generation: Cell::new(0),
+            #[cfg(debug_assertions)]
+            consuming: Cell::new(false),
+            #[cfg(debug_assertions)]
+            reentries: Cell::new(0),
+            #[cfg(feature = "metrics")]
+            spi_stats: Cell::new(SpiStats::default()),
+            preserve_crc,
+            zero_on_receive: false,
+            #[cfg(feature = "verify-checksums")]
+            drop_bad_checksums: false,
+            transmit_polls: TRANSMIT_POLLS,
+            spi_chunk: 0,
+            spi_retries: 0,
+            vlan: None,
+            rx_filter: None,
+            yield_hook: None,
+        };
+        if relocate {
+            // Errors can't be returned here, they show up as the device failing later on
+            if let Ok(mut device) = inner.device() {
+                let _ = inner.restart_receive(&mut device);
+            }
+        }
+        inner
+    }
+
+    fn reset<D>(&self, delay: &mut D) -> Result<()>
+    where
+        D: DelayUs<u16>,
+    {
+        let mut device = self.device()?;
+        let reset = device.reset_pin();
+        reset.reset();
+        delay.delay_us(RESET_DELAY_US);
+        reset.release();
+        delay.delay_us(RESET_DELAY_US);
+        self.reinitialise(&mut device)
+    }
+
+    fn reset_with_command<D>(&self, delay: &mut D) -> Result<()>
+    where
+        D: DelayUs<u16>,
+    {
+        let mut device = self.device()?;
+        device.system_reset().map_err(|_| Error::SpiTransfer)?;
+        delay.delay_us(SYSTEM_RESET_DELAY_US);
+        self.reinitialise(&mut device)
+    }
+
+    /// Bring the ENC28J60 back up after a reset
+    fn reinitialise(&self, device: &mut BankedEnc28j60<Spi, Ncs, Int, Reset>) -> Result<()> {
+        device.forget_bank();
+        device.wait_clock_ready()?;
+        self.powered_down.set(false);
+        self.configure(device)
+    }
+
+    fn self_test(&self) -> Result<bool> {
+        if self.powered_down.get() {
+            return Err(Error::Illegal);
+        }
+
+        let mut device = self.device()?;
+        let full_duplex = self.full_duplex.get();
+        self.write_duplex(&mut device, true)?;
+        device.write_phy(PhyRegister::PHCON1, PHCON1_PDPXMD | PHCON1_PLOOPBK)?;
+        let result = self.loopback(&mut device);
+        self.write_duplex(&mut device, full_duplex)?;
+        result
+    }
+
+    /// Send a test frame addressed to ourselves and check that it is received back unchanged
+    fn loopback(&self, device: &mut BankedEnc28j60<Spi, Ncs, Int, Reset>) -> Result<bool> {
+        let mac = self.mac.get();
+        let mut frame = [0; SELF_TEST_FRAME_SIZE];
+        frame[..6].copy_from_slice(&mac);
+        frame[6..12].copy_from_slice(&mac);
+        frame[12..14].copy_from_slice(&SELF_TEST_ETHERTYPE.to_be_bytes());
+        for (i, byte) in frame[14..].iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+
+        // Frames already waiting would be mistaken for the test frame
+        let mut received = [0; SELF_TEST_FRAME_SIZE];
+        while device.read(Register::EPKTCNT)? != 0 {
+            self.read_frame(device, &mut received)?;
+        }
+
+        self.transmit(device, &frame)?;
+
+        for _ in 0..SELF_TEST_POLLS {
+            if device.read(Register::EPKTCNT)? != 0 {
+                let (len, status) = self.read_frame(device, &mut received)?;
+                return Ok(status.received_ok && received[..len] == frame[..]);
+            }
+        }
+
+        Ok(false)
+    }
+
+    fn power_down(&self) -> Result<()> {
+        let mut device = self.device()?;
+        device.clear_bits(Register::ECON1, ECON1_RXEN)?;
+        device.wait_idle()?;
+        device.set_bits(Register::ECON2, ECON2_VRPS)?;
+        device.set_bits(Register::ECON2, ECON2_PWRSV)?;
+        self.powered_down.set(true);
+        Ok(())
+    }
+
+    fn power_up(&self) -> Result<()> {
+        let mut device = self.device()?;
+        device.clear_bits(Register::ECON2, ECON2_PWRSV)?;
+        device.wait_clock_ready()?;
+        self.powered_down.set(false);
+        self.enable_receive(&mut device)
+    }
+
+    /// Disable reception (ECON1.RXEN) and mask all interrupts (EIE), ignoring any errors
+    fn quiesce(&self) {
+        if let Ok(mut device) = self.device() {
+            let _ = device.clear_bits(Register::ECON1, ECON1_RXEN);
+            let _ = device.write(Register::EIE, 0);
+        }
+    }
+
+    /// Enable reception (ECON1.RXEN), unless it was disabled through [SmolEnc28j60::set_rx_enabled]
+    fn enable_receive(&self, device: &mut BankedEnc28j60<Spi, Ncs, Int, Reset>) -> Result<()> {
+        if !self.rx_enabled.get() {
+            return Ok(());
+        }
+
+        device.set_bits(Register::ECON1, ECON1_RXEN)
+    }
+
+    fn set_rx_enabled(&self, enabled: bool) -> Result<()> {
+        let mut device = self.device()?;
+        self.rx_enabled.set(enabled);
+        if !enabled {
+            device.clear_bits(Register::ECON1, ECON1_RXEN)
+        } else if self.powered_down.get() {
+            Ok(())
+        } else {
+            self.enable_receive(&mut device)
+        }
+    }
+
+    /// Assert the INT pin only while received frames are pending (EIE.INTIE, EIE.PKTIE)
+    ///
+    /// The other interrupt sources are masked, and the flags cleared that
+    /// [SmolEnc28j60::clear_interrupts] would clear, so INT isn't held asserted by anything but a
+    /// frame until [InnerEnc28j60::unmask_interrupts]. The packet interrupt is kept enabled after
+    /// that.
+    #[cfg(feature = "async")]
+    fn mask_to_packet_interrupt(&self) -> Result<()> {
+        let mut device = self.device()?;
+        let packet = EIE_INTIE | register::EIE_PKTIE;
+        device.write(Register::EIE, packet)?;
+        self.interrupts.set(self.interrupts.get() | packet);
+        self.check_overflow(&mut device)?;
+        device.clear_bits(Register::EIR, EIR_DMAIF | EIR_TXIF | EIR_TXERIF)
+    }
+
+    /// Enable the interrupts masked by [InnerEnc28j60::mask_to_packet_interrupt] again
+    #[cfg(feature = "async")]
+    fn unmask_interrupts(&self) -> Result<()> {
+        self.device()?.write(Register::EIE, self.interrupts.get())
+    }
+
+    /// Set `mask` in EIE, remembering it to be enabled again after a reset
+    fn enable_interrupts(
+        &self,
+        device: &mut BankedEnc28j60<Spi, Ncs, Int, Reset>,
+        mask: u8,
+    ) -> Result<()> {
+        device.set_bits(Register::EIE, mask)?;
+        self.interrupts.set(self.interrupts.get() | mask);
+        Ok(())
+    }
+
+    /// Program the buffer layout, MAC and PHY then enable reception and the interrupts enabled
+    /// before the reset
+    fn configure(&self, device: &mut BankedEnc28j60<Spi, Ncs, Int, Reset>) -> Result<()> {
+        self.write_layout(device)?;
+
+        self.filter.get().write(device)?;
+
+        // Registers are grouped by bank to save bank switches
+        device.with_bank(Bank::Bank2, |device| {
+            device.write(Register::MACON1, MACON1_MARXEN)?;
+            let [max_low, max_high] = MAX_FRAME_LENGTH.to_le_bytes();
+            device.write(Register::MAMXFLL, max_low)?;
+            device.write(Register::MAMXFLH, max_high)
+        })?;
+        self.write_duplex(device, self.full_duplex.get())?;
+
+        device.write_phy(PhyRegister::PHCON2, PHCON2_HDLDIS)?;
+        if let Some(leds) = self.leds.get() {
+            device.write_phy(PhyRegister::PHLCON, phlcon(leds))?;
+        }
+
+        device.with_bank(Bank::Bank3, |device| {
+            device.write_mac_address(self.mac.get())?;
+            self.paused.set(false);
+            self.write_flow_control(device, self.flow_control.get())
+        })?;
+
+        let interrupts = self.interrupts.get();
+        if interrupts & EIE_LINKIE != 0 {
+            device.write_phy(PhyRegister::PHIE, PHIE_PLNKIE | PHIE_PGEIE)?;
+        }
+        device.write(Register::EIE, interrupts)?;
+
+        self.enable_receive(device)
+    }
+
+    /// Program the buffer layout and start reading frames from the start of the RX buffer
+    fn write_layout(&self, device: &mut BankedEnc28j60<Spi, Ncs, Int, Reset>) -> Result<()> {
+        let layout = self.layout.get();
+        device.write_pointer(Pointer::ERXST, layout.rx_start)?;
+        device.write_pointer(Pointer::ERXND, layout.rx_end)?;
+        device.write_pointer(Pointer::ERXRDPT, layout.rx_end)?;
+        device.write_pointer(Pointer::ETXST, layout.tx_start)?;
+        self.next_packet.set(layout.rx_start);
+        Ok(())
+    }
+
+    fn set_layout(&self, layout: Layout) -> Result<()> {
+        if !layout.is_valid() {
+            return Err(Error::InvalidLayout);
+        }
+
+        if !layout.is_errata_safe() {
+            warn!(
+                "RX buffer at 0x{:X}, errata #5 needs it at 0x0000",
+                layout.rx_start
+            );
+        }
+
+        let mut device = self.device()?;
+        self.layout.set(layout);
+        self.prepared_len.set(0);
+        self.layout_generation
+            .set(self.layout_generation.get().wrapping_add(1));
+        self.restart_receive(&mut device)
+    }
+
+    fn flush_rx(&self) -> Result<()> {
+        if self.powered_down.get() {
+            return Err(Error::Illegal);
+        }
+
+        let mut device = self.device()?;
+        self.restart_receive(&mut device)
+    }
+
+    /// Discard every frame in the RX buffer and restart reception from the start of it
+    fn restart_receive(&self, device: &mut BankedEnc28j60<Spi, Ncs, Int, Reset>) -> Result<()> {
+        device.clear_bits(Register::ECON1, ECON1_RXEN)?;
+        device.wait_idle()?;
+        device.reset_receive()?;
+        // The frames are lost along with the read pointer, drop them from EPKTCNT
+        let pending = device.read(Register::EPKTCNT)?;
+        for _ in 0..pending {
+            device.set_bits(Register::ECON2, ECON2_PKTDEC)?;
+        }
+        self.update_stats(|stats| {
+            stats.rx_dropped = stats.rx_dropped.saturating_add(u32::from(pending));
+        });
+        self.write_layout(device)?;
+        device.clear_bits(Register::EIR, EIR_RXERIF)?;
+        self.enable_receive(device)
+    }
+
+    /// Program the MAC and PHY duplex mode along with the inter-packet gaps
+    ///
+    /// The gaps set through [SmolEnc28j60::set_ipg] are used if there are any, otherwise the
+    /// recommended gaps for the duplex mode.
+    fn write_duplex(
+        &self,
+        device: &mut BankedEnc28j60<Spi, Ncs, Int, Reset>,
+        full: bool,
+    ) -> Result<()> {
+        let macon3 = MACON3_PADCFG0 | MACON3_TXCRCEN | MACON3_FRMLNEN;
+        let (macon3, macon4, phcon1) = if full {
+            (macon3 | MACON3_FULDPX, 0, PHCON1_PDPXMD)
+        } else {
+            (macon3, MACON4_DEFER, 0)
+        };
+        let gaps = self
+            .gaps
+            .get()
+            .unwrap_or_else(|| InterPacketGaps::recommended(full));
+
+        // The PHY is written last, as the MII status register is in another bank
+        device.with_bank(Bank::Bank2, |device| {
+            device.write(Register::MACON3, macon3)?;
+            device.write(Register::MACON4, macon4)?;
+            gaps.write(device)
+        })?;
+        device.write_phy(PhyRegister::PHCON1, phcon1)
+    }
+
+    fn set_leds(&self, leds: [LedMode; 2]) -> Result<()> {
+        self.device()?
+            .write_phy(PhyRegister::PHLCON, phlcon(leds))?;
+        self.leds.set(Some(leds));
+        Ok(())
+    }
+
+    fn dump_registers<W>(&self, out: &mut W) -> Result<()>
+    where
+        W: core::fmt::Write,
+    {
+        let mut device = self.device()?;
+        for (name, register) in [
+            ("ECON1", Register::ECON1),
+            ("ECON2", Register::ECON2),
+            ("ESTAT", Register::ESTAT),
+            ("EIR", Register::EIR),
+            ("EIE", Register::EIE),
+            ("EPKTCNT", Register::EPKTCNT),
+            ("MACON1", Register::MACON1),
+            ("MACON3", Register::MACON3),
+            ("MACON4", Register::MACON4),
+        ] {
+            let value = device.read(register)?;
+            writeln!(out, "{:<8} 0x{:02X}", name, value).map_err(|_| Error::Format)?;
+        }
+
+        for (name, pointer) in [
+            ("ERXST", Pointer::ERXST),
+            ("ERXND", Pointer::ERXND),
+            ("ERXRDPT", Pointer::ERXRDPT),
+            ("ERXWRPT", Pointer::ERXWRPT),
+        ] {
+            let value = device.read_pointer(pointer)?;
+            writeln!(out, "{:<8} 0x{:04X}", name, value).map_err(|_| Error::Format)?;
+        }
+
+        for (name, register) in [
+            ("PHSTAT1", PhyRegister::PHSTAT1),
+            ("PHSTAT2", PhyRegister::PHSTAT2),
+        ] {
+            let value = device.read_phy(register)?;
+            writeln!(out, "{:<8} 0x{:04X}", name, value).map_err(|_| Error::Format)?;
+        }
+
+        Ok(())
+    }
+
+    fn set_flow_control(&self, mode: FlowControl) -> Result<()> {
+        if mode != FlowControl::Off && !self.full_duplex.get() {
+            return Err(Error::Illegal);
+        }
+
+        self.write_flow_control(&mut *self.device()?, mode)?;
+        self.flow_control.set(mode);
+        Ok(())
+    }
+
+    /// Program the pause time and start or stop sending pause frames for `mode`
+    fn write_flow_control(
+        &self,
+        device: &mut BankedEnc28j60<Spi, Ncs, Int, Reset>,
+        mode: FlowControl,
+    ) -> Result<()> {
+        let pause_time = match mode {
+            FlowControl::PauseManual(pause_time) => pause_time,
+            FlowControl::Off | FlowControl::PauseOnFull => DEFAULT_PAUSE_TIME,
+        };
+        device.write_pointer(Pointer::EPAUS, pause_time)?;
+
+        match mode {
+            FlowControl::PauseManual(_) => self.write_pause(device, true),
+            FlowControl::Off | FlowControl::PauseOnFull => self.write_pause(device, false),
+        }
+    }
+
+    /// Start sending pause frames, or release the link partner if they were being sent
+    fn write_pause(
+        &self,
+        device: &mut BankedEnc28j60<Spi, Ncs, Int, Reset>,
+        pause: bool,
+    ) -> Result<()> {
+        if pause {
+            device.write(Register::EFLOCON, EFLOCON_FCEN_PAUSE)?;
+        } else if self.paused.get() {
+            device.write(Register::EFLOCON, EFLOCON_FCEN_RELEASE)?;
+        }
+
+        self.paused.set(pause);
+        Ok(())
+    }
+
+    /// Pause the link partner while the RX buffer is over 3/4 full, for [FlowControl::PauseOnFull]
+    fn update_pause(&self, device: &mut BankedEnc28j60<Spi, Ncs, Int, Reset>) -> Result<()> {
+        if self.flow_control.get() != FlowControl::PauseOnFull {
+            return Ok(());
+        }
+
+        let layout = self.layout.get();
+        let wrpt = device.read_pointer(Pointer::ERXWRPT)?;
+        let rdpt = device.read_pointer(Pointer::ERXRDPT)?;
+        let used = layout.used(wrpt, rdpt);
+        let quarter = layout.rx_size() / 4;
+        if !self.paused.get() && used > 3 * quarter {
+            trace!("RX buffer filling up, pausing the link partner");
+            self.write_pause(device, true)
+        } else if self.paused.get() && used < quarter {
+            trace!("RX buffer drained, releasing the link partner");
+            self.write_pause(device, false)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn set_ipg(&self, gaps: InterPacketGaps) -> Result<()> {
+        gaps.write(&mut *self.device()?)?;
+        self.gaps.set(Some(gaps));
+        Ok(())
+    }
+
+    /// Program the receive filters and duplex mode with reception disabled, so that no frame is
+    /// received under a partial configuration
+    fn configure_filters(&self, filter: RxFilter, full_duplex: bool) -> Result<()> {
+        let mut device = self.device()?;
+        device.clear_bits(Register::ECON1, ECON1_RXEN)?;
+        filter.write(&mut *device)?;
+        self.write_duplex(&mut device, full_duplex)?;
+        self.filter.set(filter);
+        self.full_duplex.set(full_duplex);
+        self.enable_receive(&mut device)
+    }
+
+    fn set_duplex(&self, full: bool) -> Result<()> {
+        if !full && self.flow_control.get() != FlowControl::Off {
+            return Err(Error::Illegal);
+        }
+
+        let mut device = self.device()?;
+        if device.transmit_in_progress()? {
+            return Err(Error::Illegal);
+        }
+
+        self.write_duplex(&mut device, full)?;
+        self.full_duplex.set(full);
+        Ok(())
+    }
+
+    fn enable_link_change_interrupt(&self) -> Result<()> {
+        let mut device = self.device()?;
+        device.write_phy(PhyRegister::PHIE, PHIE_PLNKIE | PHIE_PGEIE)?;
+        self.enable_interrupts(&mut device, EIE_INTIE | EIE_LINKIE)
+    }
+
+    fn link_changed(&self) -> bool {
+        self.device()
+            .and_then(|mut device| device.read_phy(PhyRegister::PHIR))
+            .map(|phir| phir & PHIR_PLNKIF != 0)
+            .unwrap_or(false)
+    }
+
+    fn poll_link(&self) -> Result<LinkEvent> {
+        let mut device = self.device()?;
+        let flagged = device.read_phy(PhyRegister::PHIR)? & PHIR_PLNKIF != 0;
+        let up = device.read_link_up()?;
+        let changed = flagged || up != self.link_up.get();
+        self.link_up.set(up);
+
+        if !changed {
+            return Ok(LinkEvent::NoChange);
+        }
+        if !up {
+            trace!("link down");
+            return Ok(LinkEvent::LinkDown);
+        }
+
+        trace!("link up, reapplying the duplex mode");
+        self.write_duplex(&mut device, self.full_duplex.get())?;
+        if !self.powered_down.get() {
+            self.enable_receive(&mut device)?;
+        }
+        Ok(LinkEvent::LinkUp)
+    }
+
+    fn mac_address(&self) -> [u8; 6] {
+        if let Ok(mut device) = self.device() {
+            if let Ok(mac) = device.read_mac_address() {
+                self.mac.set(mac);
+            }
+        }
+
+        self.mac.get()
+    }
+
+    fn set_mac_address(&self, mac: [u8; 6]) -> Result<()> {
+        let _rx_buffer = self
+            .rx_buffer
+            .try_borrow_mut()
+            .map_err(|_| Error::Illegal)?;
+        let _tx_buffer = self
+            .tx_buffer
+            .try_borrow_mut()
+            .map_err(|_| Error::Illegal)?;
+        let mut device = self.device()?;
+        if device.transmit_in_progress()? {
+            return Err(Error::Illegal);
+        }
+
+        device.clear_bits(Register::ECON1, ECON1_RXEN)?;
+        device.wait_idle()?;
+        device.write_mac_address(mac)?;
+        self.mac.set(mac);
+        if self.powered_down.get() {
+            return Ok(());
+        }
+        self.enable_receive(&mut device)
+    }
+
+    fn revision(&self) -> u8 {
+        if let Ok(mut device) = self.device() {
+            if let Ok(revision) = device.read(Register::EREVID) {
+                self.revision.set(revision);
+            }
+        }
+
+        self.revision.get()
+    }
+
+    fn link_up(&self) -> bool {
+        if let Ok(mut device) = self.device() {
+            if let Ok(link_up) = device.read_link_up() {
+                self.link_up.set(link_up);
+            }
+        }
+
+        self.link_up.get()
+    }
+
+    fn link_info(&self) -> LinkInfo {
+        let mut full_duplex = self.full_duplex.get();
+        if let Ok(mut device) = self.device() {
+            if let Ok((link_up, phy_full_duplex)) = device.read_link_status() {
+                self.link_up.set(link_up);
+                full_duplex = phy_full_duplex;
+            }
+        }
+
+        LinkInfo {
+            up: self.link_up.get(),
+            full_duplex,
+            speed: LinkSpeed::TenBaseT,
+        }
+    }
+
+    fn device(&self) -> Result<RefMut<'_, BankedEnc28j60<Spi, Ncs, Int, Reset>>> {
+        let device = self.device.try_borrow_mut().map_err(|_| {
+            trace!("device in use");
+            Error::Illegal
+        })?;
+        RefMut::filter_map(device, Option::as_mut).map_err(|_| Error::Illegal)
+    }
+
+    /// Longest frame that can be handed to a TX token, making room for the VLAN tag if any
+    fn max_frame_len(&self) -> usize {
+        let max = self.capacity.min(BUFFER_SIZE);
+        match self.vlan {
+            Some(_) => max.saturating_sub(VLAN_TAG_SIZE),
+            None => max,
+        }
+    }
+
+    fn pending_packets(&self) -> u8 {
+        self.device()
+            .and_then(|mut device| device.read(Register::EPKTCNT))
+            .unwrap_or(0)
+    }
+
+    fn rx_fifo_bytes(&self) -> u16 {
+        let pointers = self.device().and_then(|mut device| {
+            Ok((
+                device.read_pointer(Pointer::ERXWRPT)?,
+                device.read_pointer(Pointer::ERXRDPT)?,
+            ))
+        });
+
+        match pointers {
+            Ok((wrpt, rdpt)) => self.layout.get().used(wrpt, rdpt),
+            Err(_) => 0,
+        }
+    }
+
+    /// Whether a frame is waiting in the RX ring or the ENC28J60
+    fn rx_pending(&self) -> bool {
+        #[cfg(feature = "heapless")]
+        if matches!(self.ring.try_borrow().as_deref(), Ok(Some(ring)) if !ring.is_empty()) {
+            return true;
+        }
+
+        self.pending_packets() != 0
+    }
+
+    /// Move frames waiting in the ENC28J60 into the RX ring until it is full
+    ///
+    /// Frames the ENC28J60 flagged as bad are dropped as for tokens, any other error leaves the
+    /// remaining frames for the next poll.
+    #[cfg(feature = "heapless")]
+    fn fill_ring(&self) {
+        let mut ring = match self.ring.try_borrow_mut() {
+            Ok(ring) => ring,
+            Err(_) => return,
+        };
+        let ring = match ring.as_mut() {
+            Some(ring) => ring,
+            None => return,
+        };
+
+        while !ring.is_full() && self.pending_packets() != 0 {
+            let frame = match ring.push_empty() {
+                Some(frame) => frame,
+                None => return,
+            };
+            let _ = frame.resize_default(BUFFER_SIZE);
+            match self.receive(frame) {
+                Ok(len) => frame.truncate(len),
+                Err(Error::InvalidFrame) => ring.discard_back(),
+                Err(_) => {
+                    ring.discard_back();
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Whether a transmission is in progress, either through a token or still on the wire
+    fn transmit_busy(&self) -> bool {
+        if self.tx_buffer.try_borrow_mut().is_err() {
+            return true;
+        }
+
+        self.device()
+            .and_then(|mut device| device.transmit_in_progress())
+            .unwrap_or(true)
+    }
+
+    fn set_filter(&self, filter: RxFilter) -> Result<()> {
+        filter.write(&mut *self.device()?)?;
+        self.filter.set(filter);
+        Ok(())
+    }
+
+    /// Transmit a frame, waiting for the transmission to finish
+    fn transmit(
+        &self,
+        device: &mut BankedEnc28j60<Spi, Ncs, Int, Reset>,
+        frame: &[u8],
+    ) -> Result<()> {
+        self.transmit_with(device, |device| {
+            device.write_buffer_chunked(frame, self.spi_chunk)?;
+            Ok(frame.len())
+        })
+        .map(|_| ())
+    }
+
+    /// Transmit the frame `write` copies into the transmit buffer, waiting for the transmission to
+    /// finish
+    ///
+    /// `write` returns the length of the frame, which is also returned.
+    fn transmit_with<F>(
+        &self,
+        device: &mut BankedEnc28j60<Spi, Ncs, Int, Reset>,
+        write: F,
+    ) -> Result<usize>
+    where
+        F: FnOnce(&mut BankedEnc28j60<Spi, Ncs, Int, Reset>) -> Result<usize>,
+    {
+        let tx_start = self.layout.get().tx_start;
+        self.transmit_from(device, tx_start, |device| {
+            device.prepare_transmit(tx_start)?;
+            write(device)
+        })
+    }
+
+    /// Transmit the frame following the control byte at `tx_start`, once `write` has written it
+    /// and returned its length
+    fn transmit_from<F>(
+        &self,
+        device: &mut BankedEnc28j60<Spi, Ncs, Int, Reset>,
+        tx_start: u16,
+        write: F,
+    ) -> Result<usize>
+    where
+        F: FnOnce(&mut BankedEnc28j60<Spi, Ncs, Int, Reset>) -> Result<usize>,
+    {
+        // Errata #12: the transmit logic can stall, so it is reset before every transmission
+        device.reset_transmit()?;
+        let len = write(device)?;
+        if len > BUFFER_SIZE {
+            return Err(Error::FrameTooLong);
+        }
+        device.start_transmit(tx_start, len)?;
+        let result = device.wait_transmit(self.transmit_polls, self.yield_hook);
+        if result == Err(Error::TransmitTimeout) {
+            // The transmission was aborted before a status vector was written
+            self.last_tx_status.set(None);
+            return Err(Error::TransmitTimeout);
+        }
+
+        // The control byte comes before the frame, so it ends at tx_start + len
+        let tx_end = tx_start.wrapping_add(len as u16);
+        let status = TxStatus::parse(device.read_transmit_status(tx_end)?);
+        if status.crc_error || status.late_collision {
+            self.update_stats(|stats| stats.tx_errors = stats.tx_errors.saturating_add(1));
+        }
+        self.last_tx_status.set(Some(status));
+        result.map(|_| len)
+    }
+
+    fn prepare_frame(&self, buf: &[u8]) -> Result<FrameHandle> {
+        if buf.len() > BUFFER_SIZE {
+            return Err(Error::FrameTooLong);
+        }
+
+        let layout = self.layout.get();
+        let start =
+            usize::from(layout.tx_start) + TX_BUFFER_SIZE + usize::from(self.prepared_len.get());
+        let size = 1 + buf.len() + TSV_SIZE;
+        let end = start + size - 1;
+        if end > usize::from(BUFFER_END)
+            || (start <= usize::from(layout.rx_end) && end >= usize::from(layout.rx_start))
+        {
+            return Err(Error::InvalidLayout);
+        }
+
+        let mut device = self.device()?;
+        let start = start as u16;
+        self.retry(|| {
+            device.prepare_transmit(start)?;
+            device.write_buffer_chunked(buf, self.spi_chunk)
+        })
+        .map_err(|error| self.fail(error))?;
+        self.prepared_len
+            .set(self.prepared_len.get().wrapping_add(size as u16));
+
+        Ok(FrameHandle {
+            start,
+            len: buf.len() as u16,
+            generation: self.layout_generation.get(),
+        })
+    }
+
+    fn retransmit(&self, handle: &FrameHandle) -> Result<()> {
+        if handle.generation != self.layout_generation.get() {
+            return Err(Error::Illegal);
+        }
+
+        let mut device = self.device()?;
+        let len = usize::from(handle.len);
+        let result = self.retry(|| self.transmit_from(&mut device, handle.start, |_| Ok(len)));
+        self.sent(result)
+    }
+
+    fn flush(&self) -> Result<()> {
+        let mut device = self.device()?;
+        if !device.transmit_in_progress()? {
+            return Ok(());
+        }
+
+        device
+            .wait_transmit(self.transmit_polls, self.yield_hook)
+            .map_err(|error| self.fail(error))
+    }
+
+    fn send(&self, buffer: &[u8]) -> Result<()> {
+        self.send_chained(&[buffer])
+    }
+
+    /// Transmit a frame made of `segments` in order, retrying on bus errors as set through
+    /// [SmolEnc28j60::set_spi_retries]
+    fn send_chained(&self, segments: &[&[u8]]) -> Result<()> {
+        let len: usize = segments.iter().map(|segment| segment.len()).sum();
+        let mut device = self.device()?;
+        let result = self.retry(|| {
+            self.transmit_with(&mut device, |device| {
+                for segment in segments {
+                    device.write_buffer_chunked(segment, self.spi_chunk)?;
+                }
+                Ok(len)
+            })
+        });
+        self.sent(result)
+    }
+
+    /// Transmit a frame through [InnerEnc28j60::transmit_with], keeping the statistics
+    fn send_with<F>(&self, write: F) -> Result<()>
+    where
+        F: FnOnce(&mut BankedEnc28j60<Spi, Ncs, Int, Reset>) -> Result<usize>,
+    {
+        let mut device = self.device()?;
+        let result = self.transmit_with(&mut device, write);
+        self.sent(result)
+    }
+
+    /// Keep the statistics for a transmission returning the length of the frame
+    fn sent(&self, result: Result<usize>) -> Result<()> {
+        match result {
+            Ok(len) => {
+                trace!("sent frame of {} bytes", len);
+                self.update_stats(|stats| {
+                    stats.tx_packets = stats.tx_packets.saturating_add(1);
+                    stats.tx_bytes = stats.tx_bytes.saturating_add(len as u64);
+                });
+                Ok(())
+            }
+            Err(error) => {
+                warn!("transmit failed: {:?}", error);
+                Err(self.fail(error))
+            }
+        }
+    }
+
+    fn receive(&self, buffer: &mut [u8]) -> Result<usize> {
+        self.receive_limited(buffer, u8::MAX, &mut 0)
+    }
+
+    /// Receive the next frame accepted by the software receive filter, reading at most `limit`
+    /// frames and adding those read to `read`
+    ///
+    /// No more frames are read than were waiting when this was called, so frames arriving in the
+    /// meantime can't keep the filter looping. [Error::Filtered] is returned once the limit is
+    /// reached with every frame dropped.
+    fn receive_limited(&self, buffer: &mut [u8], limit: u8, read: &mut u8) -> Result<usize> {
+        let mut device = self.device()?;
+        // Tokens are handed out while a frame is waiting, but another token of a shared device may
+        // have read it since
+        let pending = self
+            .retry(|| device.read(Register::EPKTCNT))
+            .map_err(|error| self.fail(error))?;
+        if pending == 0 {
+            return Err(Error::NoFrame);
+        }
+
+        let limit = limit.min(pending);
+        let mut filtered = 0;
+        let result = self
+            .check_overflow(&mut device)
+            .and_then(|_| self.update_pause(&mut device))
+            .and_then(|_| loop {
+                let frame = self.retry(|| self.read_frame(&mut device, buffer));
+                // A bad frame is freed all the same
+                if matches!(frame, Ok(_) | Err(Error::InvalidFrame)) {
+                    *read = read.saturating_add(1);
+                }
+                let (len, status) = frame?;
+                if !status.received_ok {
+                    break Err(Error::InvalidFrame);
+                }
+
+                #[cfg(feature = "verify-checksums")]
+                if !checksum::verify(&buffer[..len]) {
+                    trace!("received frame with a bad checksum");
+                    self.update_stats(|stats| {
+                        stats.rx_checksum_errors = stats.rx_checksum_errors.saturating_add(1)
+                    });
+                    if self.drop_bad_checksums {
+                        break Err(Error::InvalidFrame);
+                    }
+                }
+
+                match self.rx_filter {
+                    Some(filter) if !filter(&buffer[..len]) => {
+                        self.update_stats(|stats| {
+                            stats.rx_filtered = stats.rx_filtered.saturating_add(1)
+                        });
+                        filtered += 1;
+                        if filtered == limit {
+                            break Err(Error::Filtered);
+                        }
+                    }
+                    _ => break Ok(len),
+                }
+            });
+
+        match result {
+            Ok(len) => {
+                trace!("received frame of {} bytes", len);
+                self.update_stats(|stats| {
+                    stats.rx_packets = stats.rx_packets.saturating_add(1);
+                    stats.rx_bytes = stats.rx_bytes.saturating_add(len as u64);
+                });
+                Ok(len)
+            }
+            Err(Error::Filtered) => {
+                trace!("every waiting frame was filtered");
+                Err(Error::Filtered)
+            }
+            Err(error) => {
+                self.update_stats(|stats| stats.rx_dropped = stats.rx_dropped.saturating_add(1));
+                warn!("receive failed: {:?}", error);
+                Err(self.fail(error))
+            }
+        }
+    }
+
+    /// Run `f` again while it fails with a bus error, up to [SmolEnc28j60::set_spi_retries] times
+    fn retry<T, F>(&self, mut f: F) -> Result<T>
+    where
+        F: FnMut() -> Result<T>,
+    {
+        let mut retries = self.spi_retries;
+        loop {
+            match f() {
+                Err(error) if error.is_bus_error() && retries > 0 => {
+                    trace!("retrying after {:?}", error);
+                    retries -= 1;
+                    self.update_stats(|stats| {
+                        stats.spi_retries = stats.spi_retries.saturating_add(1)
+                    });
+                }
+                result => return result,
+            }
+        }
+    }
 
-/// Maximum message size
-const BUFFER_SIZE: usize = (MAX_FRAME_LENGTH - CRC_SZ) as usize;
+    /// Receive a frame into `buffer` and hand it to `f`, for an RX token consumed at `timestamp`
+    fn consume_into<R, F>(&self, buffer: &mut [u8], timestamp: Instant, f: F) -> smoltcp::Result<R>
+    where
+        F: FnOnce(&mut [u8]) -> smoltcp::Result<R>,
+    {
+        let len = self.receive(buffer)?;
+        self.last_rx_instant.set(Some(timestamp));
+        if self.zero_on_receive {
+            buffer[len..].fill(0);
+        }
+        self.consuming(|| f(&mut buffer[..len]))
+    }
 
-/// Wrapper for enc28j60 that implements the smoltcp Device trait
-pub struct SmolEnc28j60<Spi, Ncs, Int, Reset>
-where
-    Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8>,
-    Ncs: OutputPin,
-    Int: enc28j60::IntPin,
-    Reset: enc28j60::ResetPin,
-{
-    device: InnerEnc28j60<Spi, Ncs, Int, Reset>,
+    /// Run the closure of a token, tracking it so a token consumed from within can be counted in
+    /// [SmolEnc28j60::reentrant_consumes]
+    fn consuming<R>(&self, f: impl FnOnce() -> R) -> R {
+        #[cfg(debug_assertions)]
+        let outer = self.consuming.replace(true);
+        let result = f();
+        #[cfg(debug_assertions)]
+        self.consuming.set(outer);
+        result
+    }
+
+    /// Return the buffer of an RX token to the pool it was taken from
+    #[cfg(feature = "heapless")]
+    fn release(&self, buffer: &'static mut [u8]) {
+        if let Ok(mut pool) = self.pool.try_borrow_mut() {
+            if let Some(pool) = pool.as_mut() {
+                pool.give(buffer);
+            }
+        }
+    }
+
+    fn update_stats<F>(&self, f: F)
+    where
+        F: FnOnce(&mut Stats),
+    {
+        let mut stats = self.stats.get();
+        f(&mut stats);
+        self.stats.set(stats);
+    }
+
+    fn service_interrupts(&self) -> Result<InterruptStatus> {
+        let mut device = self.device()?;
+        let eir = device.read(Register::EIR)?;
+        let status = InterruptStatus {
+            packet: eir & EIR_PKTIF != 0,
+            transmitted: eir & EIR_TXIF != 0,
+            transmit_error: eir & EIR_TXERIF != 0,
+            receive_error: eir & EIR_RXERIF != 0,
+            link_changed: eir & EIR_LINKIF != 0,
+        };
+
+        self.check_overflow(&mut device)?;
+        device.clear_bits(Register::EIR, EIR_DMAIF | EIR_TXIF | EIR_TXERIF)?;
+        // LINKIF can't be cleared directly, it clears along with PHIR.PLNKIF when PHIR is read
+        if status.link_changed {
+            device.read_phy(PhyRegister::PHIR)?;
+        }
+
+        Ok(status)
+    }
+
+    fn clear_interrupts(&self) -> Result<()> {
+        let mut device = self.device()?;
+        self.check_overflow(&mut device)?;
+        device.clear_bits(Register::EIR, EIR_DMAIF | EIR_TXIF | EIR_TXERIF)
+    }
+
+    /// Count and clear a receive buffer overflow (EIR.RXERIF)
+    ///
+    /// The ENC28J60 sets RXERIF when a frame arrives that doesn't fit in the receive buffer, which
+    /// means frames aren't being read out fast enough.
+    fn check_overflow(&self, device: &mut BankedEnc28j60<Spi, Ncs, Int, Reset>) -> Result<()> {
+        if device.read(Register::EIR)? & EIR_RXERIF != 0 {
+            device.clear_bits(Register::EIR, EIR_RXERIF)?;
+            self.fail(Error::BufferOverflow);
+            self.update_stats(|stats| {
+                stats.rx_overflows = stats.rx_overflows.saturating_add(1);
+                stats.rx_dropped = stats.rx_dropped.saturating_add(1);
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Read the next frame out of the receive buffer, returning the length and status of the frame
+    ///
+    /// The frame is freed from the receive buffer whatever its status. A status vector claiming a
+    /// frame longer than MAMXFL can only come from a corrupted header, so the frame is freed
+    /// without being read and [Error::InvalidFrame] returned. So is a next packet pointer outside
+    /// the RX buffer, which leaves no way to find the following frames: reception is restarted,
+    /// discarding every waiting frame.
+    ///
+    /// The read pointer is tracked here rather than in the driver so it stays correct across a
+    /// reset. The driver's receive path is never used, so from the first frame read here its own
+    /// pointer is stale.
+    fn read_frame(
+        &self,
+        device: &mut BankedEnc28j60<Spi, Ncs, Int, Reset>,
+        buffer: &mut [u8],
+    ) -> Result<(usize, RxStatus)> {
+        self.read_frame_with(device, |device, len, _| {
+            let len = len.min(buffer.len());
+            device.read_buffer_chunked(&mut buffer[..len], self.spi_chunk)?;
+            Ok(len)
+        })
+    }
+
+    /// Read the next frame out of the receive buffer through `read`, as for
+    /// [InnerEnc28j60::read_frame]
+    ///
+    /// `read` is called with the length and status of the frame once ERDPT points at its data,
+    /// and returns the number of bytes it read. The frame is left in the receive buffer if `read`
+    /// fails.
+    fn read_frame_with<F>(
+        &self,
+        device: &mut BankedEnc28j60<Spi, Ncs, Int, Reset>,
+        read: F,
+    ) -> Result<(usize, RxStatus)>
+    where
+        F: FnOnce(&mut BankedEnc28j60<Spi, Ncs, Int, Reset>, usize, RxStatus) -> Result<usize>,
+    {
+        device.write_pointer(Pointer::ERDPT, self.next_packet.get())?;
+
+        let mut header = [0; RX_HEADER_SIZE];
+        device.read_buffer(&mut header)?;
+        let next_packet = u16::from_le_bytes([header[0], header[1]]);
+        let layout = self.layout.get();
+        if !layout.holds_frame_at(next_packet) {
+            warn!(
+                "next packet pointer 0x{:X} out of the RX buffer",
+                next_packet
+            );
+            // Free this frame, the rest are counted as dropped by the restart
+            device.set_bits(Register::ECON2, ECON2_PKTDEC)?;
+            self.restart_receive(device)?;
+            return Err(Error::InvalidFrame);
+        }
+        let status = RxStatus::parse([header[2], header[3], header[4], header[5]]);
+        self.last_rx_status.set(Some(status));
+        if status.crc_error {
+            self.update_stats(|stats| stats.rx_crc_errors = stats.rx_crc_errors.saturating_add(1));
+        }
+        if status.length_check_error || status.dribble_nibble {
+            self.update_stats(|stats| {
+                stats.rx_align_errors = stats.rx_align_errors.saturating_add(1)
+            });
+        }
+
+        let oversize = status.byte_count > MAX_FRAME_LENGTH;
+        let len = if oversize {
+            warn!("dropping frame claiming {} bytes", status.byte_count);
+            0
+        } else if self.preserve_crc {
+            usize::from(status.byte_count)
+        } else {
+            usize::from(status.byte_count.saturating_sub(CRC_SZ))
+        };
+        let len = read(device, len, status)?;
+
+        // Errata #14: ERXRDPT must be odd, so free everything up to the byte before the next frame
+        let rdpt = if next_packet == layout.rx_start {
+            layout.rx_end
+        } else {
+            next_packet.wrapping_sub(1)
+        };
+        device.write_pointer(Pointer::ERXRDPT, rdpt)?;
+        device.set_bits(Register::ECON2, ECON2_PKTDEC)?;
+        self.next_packet.set(next_packet);
+
+        if oversize {
+            return Err(Error::InvalidFrame);
+        }
+        Ok((len, status))
+    }
+
+    fn receive_to_ring<W>(&self, ring: &mut W) -> Result<usize>
+    where
+        W: RingWriter + ?Sized,
+    {
+        let mut device = self.device()?;
+        if device.read(Register::EPKTCNT)? == 0 {
+            return Ok(0);
+        }
+        self.check_overflow(&mut device)?;
+        self.update_pause(&mut device)?;
+
+        let result = self.read_frame_with(&mut device, |device, len, status| {
+            // Bad frames are freed without being read into the ring
+            if !status.received_ok {
+                return Ok(0);
+            }
+            if len > ring.free() {
+                return Err(Error::FrameTooLong);
+            }
+
+            let mut read = 0;
+            while read < len {
+                let window = ring.window();
+                let part = window.len().min(len - read);
+                if part == 0 {
+                    break;
+                }
+                device.read_buffer_chunked(&mut window[..part], self.spi_chunk)?;
+                ring.commit(part);
+                read += part;
+            }
+            Ok(read)
+        });
+
+        match result {
+            Ok((len, status)) if status.received_ok => {
+                trace!("received frame of {} bytes into the ring", len);
+                self.update_stats(|stats| {
+                    stats.rx_packets = stats.rx_packets.saturating_add(1);
+                    stats.rx_bytes = stats.rx_bytes.saturating_add(len as u64);
+                });
+                Ok(len)
+            }
+            // The frame is left waiting until the ring has room for it
+            Err(Error::FrameTooLong) => Err(Error::FrameTooLong),
+            result => {
+                let error = result.err().unwrap_or(Error::InvalidFrame);
+                self.update_stats(|stats| stats.rx_dropped = stats.rx_dropped.saturating_add(1));
+                warn!("receive failed: {:?}", error);
+                Err(self.fail(error))
+            }
+        }
+    }
+
+    /// Count a token that found its frame buffer in use
+    ///
+    /// This isn't recorded as the last error, the buffer is free again once the other token is
+    /// consumed.
+    fn busy(&self, buffer: &str) -> Error {
+        trace!("{} buffer in use", buffer);
+        #[cfg(debug_assertions)]
+        if self.consuming.get() {
+            trace!("{} token consumed from within another token", buffer);
+            self.reentries.set(self.reentries.get().saturating_add(1));
+        }
+        self.update_stats(|stats| stats.buffer_busy = stats.buffer_busy.saturating_add(1));
+        Error::BufferBusy
+    }
+
+    /// Record an error so it can be inspected through [SmolEnc28j60::last_error]
+    fn fail(&self, error: Error) -> Error {
+        self.last_error.set(Some(error));
+        error
+    }
 }
 
-impl<Spi, Ncs, Int, Reset> From<Enc28j60<Spi, Ncs, Int, Reset>>
-    for SmolEnc28j60<Spi, Ncs, Int, Reset>
-where
-    Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8>,
-    Ncs: OutputPin,
-    Int: enc28j60::IntPin,
-    Reset: enc28j60::ResetPin,
-{
-    fn from(enc: Enc28j60<Spi, Ncs, Int, Reset>) -> Self {
-        SmolEnc28j60 {
-            device: InnerEnc28j60::new(enc),
+/// SPI transfer counters, see [SmolEnc28j60::spi_stats]
+///
+/// Only transfers of buffer memory, which carry the frames, are counted. Control register accesses
+/// are a few bytes each and left out. All counters saturate rather than wrap.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SpiStats {
+    /// Bytes of buffer memory read and written, excluding the opcode byte of each transfer
+    pub bytes: u64,
+    /// Buffer memory transfers, each a separate SPI transaction
+    pub transfers: u32,
+}
+
+/// Packet and byte counters
+///
+/// All counters saturate rather than wrap.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Frames received and handed to smoltcp
+    pub rx_packets: u32,
+    /// Frames transmitted
+    pub tx_packets: u32,
+    /// Bytes received and handed to smoltcp
+    pub rx_bytes: u64,
+    /// Bytes transmitted
+    pub tx_bytes: u64,
+    /// Frames that could not be received, or were lost to a receive buffer overflow
+    pub rx_dropped: u32,
+    /// Receive buffer overflows, each losing at least one frame
+    pub rx_overflows: u32,
+    /// Frames transmitted with a CRC error or a late collision, see [TxStatus]
+    pub tx_errors: u32,
+    /// Tokens that found their frame buffer still in use by another token, see
+    /// [Error::BufferBusy]
+    pub buffer_busy: u32,
+    /// Frames received with a CRC error, see [RxStatus::crc_error]
+    ///
+    /// The ENC28J60 is set to discard frames with a bad CRC itself (ERXFCON.CRCEN), so this only
+    /// counts if that filter is turned off, such as through `SmolEnc28j60::write_reg`.
+    pub rx_crc_errors: u32,
+    /// Frames received with a length check error or a dribble nibble, see
+    /// [RxStatus::length_check_error] and [RxStatus::dribble_nibble]
+    pub rx_align_errors: u32,
+    /// Frames dropped by the software receive filter, see [SmolEnc28j60::set_rx_filter]
+    pub rx_filtered: u32,
+    /// SPI operations retried after a bus error, see [SmolEnc28j60::set_spi_retries]
+    pub spi_retries: u32,
+    /// Frames received with a bad IPv4, UDP or TCP checksum, see
+    /// [SmolEnc28j60::set_drop_bad_checksums]
+    #[cfg(feature = "verify-checksums")]
+    pub rx_checksum_errors: u32,
+}
+
+/// Frame loaded into the buffer memory of the ENC28J60, see [SmolEnc28j60::prepare_frame]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHandle {
+    /// Address of the control byte preceding the frame
+    start: u16,
+    len: u16,
+    /// [InnerEnc28j60::layout_generation] when the frame was loaded
+    generation: u16,
+}
+
+/// Transmit status vector written by the ENC28J60 after each transmission
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxStatus {
+    /// Bytes in the frame, not counting collisions
+    pub byte_count: u16,
+    /// Collisions during the transmission
+    pub collision_count: u8,
+    /// The CRC of the frame didn't match the one generated
+    pub crc_error: bool,
+    /// The transmission was aborted by a late collision
+    pub late_collision: bool,
+    /// The transmission was deferred as the medium was busy
+    pub defer: bool,
+    /// The frame was longer than MAMXFL
+    pub giant: bool,
+}
+
+impl TxStatus {
+    fn parse(tsv: [u8; TSV_SIZE]) -> Self {
+        TxStatus {
+            byte_count: u16::from_le_bytes([tsv[0], tsv[1]]),
+            collision_count: tsv[2] & 0x0F,
+            crc_error: tsv[2] & (1 << 4) != 0,
+            late_collision: tsv[3] & (1 << 5) != 0,
+            defer: tsv[3] & (1 << 2) != 0,
+            giant: tsv[3] & (1 << 6) != 0,
         }
     }
 }
 
-impl<'a, Spi, Ncs, Int, Reset> SmolDevice<'a> for SmolEnc28j60<Spi, Ncs, Int, Reset>
-where
-    Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8> + 'a,
-    Ncs: OutputPin + 'a,
-    Int: enc28j60::IntPin + 'a,
-    Reset: enc28j60::ResetPin + 'a,
-{
-    type RxToken = RxToken<'a, Spi, Ncs, Int, Reset>;
+/// Receive status vector the ENC28J60 writes ahead of each received frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RxStatus {
+    /// Bytes in the frame, including its CRC
+    pub byte_count: u16,
+    /// The CRC of the frame didn't match the one calculated
+    pub crc_error: bool,
+    /// The length/type field is a length that doesn't match the frame
+    pub length_check_error: bool,
+    /// The frame ended on a partial byte, which was dropped
+    pub dribble_nibble: bool,
+    /// The frame was sent to a multicast address
+    pub multicast: bool,
+    /// The frame was sent to the broadcast address
+    pub broadcast: bool,
+    /// The frame was received without errors
+    pub received_ok: bool,
+}
 
-    type TxToken = TxToken<'a, Spi, Ncs, Int, Reset>;
+impl RxStatus {
+    fn parse(rsv: [u8; 4]) -> Self {
+        RxStatus {
+            byte_count: u16::from_le_bytes([rsv[0], rsv[1]]),
+            crc_error: rsv[2] & (1 << 4) != 0,
+            length_check_error: rsv[2] & (1 << 5) != 0,
+            received_ok: rsv[2] & (1 << 7) != 0,
+            multicast: rsv[3] & (1 << 0) != 0,
+            broadcast: rsv[3] & (1 << 1) != 0,
+            dribble_nibble: rsv[3] & (1 << 2) != 0,
+        }
+    }
+}
 
-    fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
-        Some((
-            RxToken {
-                lower: &self.device,
-            },
-            TxToken {
-                lower: &self.device,
-            },
-        ))
+/// Speed of the link, see [SmolEnc28j60::link_speed]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkSpeed {
+    /// 10 Mbit/s over twisted pair, the only speed of the ENC28J60
+    TenBaseT,
+}
+
+/// Link status of the PHY, see [SmolEnc28j60::link_info]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkInfo {
+    /// Whether the link is up (PHSTAT2.LSTAT)
+    pub up: bool,
+    /// Whether the PHY is in full duplex (PHSTAT2.DPXSTAT)
+    pub full_duplex: bool,
+    /// Speed of the link
+    pub speed: LinkSpeed,
+}
+
+/// Interrupt sources that fired, see [SmolEnc28j60::service_interrupts]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptStatus {
+    /// Received frames are waiting (EIR.PKTIF)
+    pub packet: bool,
+    /// A transmission finished (EIR.TXIF)
+    pub transmitted: bool,
+    /// A transmission was aborted (EIR.TXERIF)
+    pub transmit_error: bool,
+    /// The receive buffer overflowed and frames were lost (EIR.RXERIF)
+    pub receive_error: bool,
+    /// The link went up or down (EIR.LINKIF)
+    pub link_changed: bool,
+}
+
+/// Change in the link status, see [SmolEnc28j60::poll_link]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkEvent {
+    /// The link came up, and the MAC was set up for it again
+    LinkUp,
+    /// The link went down
+    LinkDown,
+    /// The link is as it was at the last poll
+    NoChange,
+}
+
+/// What an LED driven by the ENC28J60 shows, see [SmolEnc28j60::set_leds]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LedMode {
+    /// Lit while transmitting
+    TransmitActivity,
+    /// Lit while receiving
+    ReceiveActivity,
+    /// Lit on collisions
+    CollisionActivity,
+    /// Lit while the link is up
+    Link,
+    /// Lit in full duplex
+    Duplex,
+    /// Lit while transmitting or receiving
+    Activity,
+    /// Always on
+    On,
+    /// Always off
+    Off,
+    /// Blinking fast
+    BlinkFast,
+    /// Blinking slow
+    BlinkSlow,
+    /// Link status and receive activity
+    LinkReceive,
+    /// Link status and transmit or receive activity
+    LinkActivity,
+    /// Duplex mode and collision activity
+    DuplexCollision,
+}
+
+impl LedMode {
+    /// LACFG/LBCFG value
+    fn config(self) -> u16 {
+        match self {
+            LedMode::TransmitActivity => 0b0001,
+            LedMode::ReceiveActivity => 0b0010,
+            LedMode::CollisionActivity => 0b0011,
+            LedMode::Link => 0b0100,
+            LedMode::Duplex => 0b0101,
+            LedMode::Activity => 0b0111,
+            LedMode::On => 0b1000,
+            LedMode::Off => 0b1001,
+            LedMode::BlinkFast => 0b1010,
+            LedMode::BlinkSlow => 0b1011,
+            LedMode::LinkReceive => 0b1100,
+            LedMode::LinkActivity => 0b1101,
+            LedMode::DuplexCollision => 0b1110,
+        }
     }
+}
 
-    fn transmit(&'a mut self) -> Option<Self::TxToken> {
-        Some(TxToken {
-            lower: &self.device,
+/// Whether the ENC28J60 sends pause frames, see [SmolEnc28j60::set_flow_control]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowControl {
+    /// No pause frames are sent
+    Off,
+    /// Pause frames are sent while the RX buffer is over 3/4 full, until it drains below 1/4
+    ///
+    /// The ENC28J60 has no threshold of its own, so the fill level is checked each time a frame is
+    /// received.
+    PauseOnFull,
+    /// Pause frames with the given pause time, in units of 512 bit times, are sent until flow
+    /// control is set to another mode
+    PauseManual(u16),
+}
+
+/// PHLCON value for the LEDA and LEDB modes, with events stretched to the shortest time (40 ms)
+fn phlcon([leda, ledb]: [LedMode; 2]) -> u16 {
+    PHLCON_RESERVED | leda.config() << 8 | ledb.config() << 4 | PHLCON_STRCH
+}
+
+/// Partitioning of the ENC28J60 buffer memory between RX and TX
+#[derive(Clone, Copy)]
+struct Layout {
+    rx_start: u16,
+    rx_end: u16,
+    tx_start: u16,
+}
+
+impl Layout {
+    fn read<D>(device: &mut D) -> Result<Self>
+    where
+        D: Registers,
+    {
+        Ok(Layout {
+            rx_start: device.read_pointer(Pointer::ERXST)?,
+            rx_end: device.read_pointer(Pointer::ERXND)?,
+            tx_start: device.read_pointer(Pointer::ETXST)?,
         })
     }
 
-    fn capabilities(&self) -> smoltcp::phy::DeviceCapabilities {
-        let mut cap = DeviceCapabilities::default();
-        cap.medium = phy::Medium::Ethernet;
-        cap.max_transmission_unit = BUFFER_SIZE;
-        cap.max_burst_size = Some(1);
-        cap
+    /// Whether a frame can start at `address`: within the RX buffer and, as frames are padded to
+    /// an even length, on an even address
+    fn holds_frame_at(self, address: u16) -> bool {
+        (self.rx_start..=self.rx_end).contains(&address) && address % 2 == 0
+    }
+
+    /// Size of the RX buffer in bytes
+    fn rx_size(self) -> u16 {
+        self.rx_end.wrapping_sub(self.rx_start).wrapping_add(1)
+    }
+
+    /// Bytes of the RX buffer in use between the read pointer, which the ENC28J60 doesn't free
+    /// the byte at, and the write pointer
+    fn used(self, wrpt: u16, rdpt: u16) -> u16 {
+        let size = u32::from(self.rx_size());
+        let offset = |pointer: u16| u32::from(pointer.wrapping_sub(self.rx_start)) % size;
+        let used = (offset(wrpt) + 2 * size - offset(rdpt) - 1) % size;
+        used as u16
+    }
+
+    /// RX buffer of `rx_size` bytes at 0x0000 as errata #5 requires, followed by the TX buffer
+    fn with_rx_size(rx_size: u16) -> Self {
+        Layout {
+            rx_start: ERRATA_RX_START,
+            rx_end: rx_size.wrapping_sub(1),
+            tx_start: rx_size,
+        }
+    }
+
+    /// Whether the RX buffer starts at 0x0000 as errata #5 requires
+    fn is_errata_safe(self) -> bool {
+        self.rx_start == ERRATA_RX_START
+    }
+
+    /// Whether the RX buffer is well formed and clear of the TX buffer, see
+    /// [SmolEnc28j60::set_buffer_split] and [SmolEnc28j60::set_tx_start]
+    fn is_valid(self) -> bool {
+        let tx_end = usize::from(self.tx_start) + TX_BUFFER_SIZE - 1;
+        self.rx_start & 1 == 0
+            && self.rx_end & 1 == 1
+            && self.rx_start < self.rx_end
+            && self.rx_end <= BUFFER_END
+            && tx_end <= usize::from(BUFFER_END)
+            && (tx_end < usize::from(self.rx_start) || self.tx_start > self.rx_end)
     }
 }
 
-struct InnerEnc28j60<Spi, Ncs, Int, Reset>
-where
-    Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8>,
-    Ncs: OutputPin,
-    Int: enc28j60::IntPin,
-    Reset: enc28j60::ResetPin,
-{
-    device: RefCell<Enc28j60<Spi, Ncs, Int, Reset>>,
-    buffer: RefCell<[u8; BUFFER_SIZE]>,
+impl Default for Layout {
+    /// RX buffer of [SmolEnc28j60::init] at 0x0000 as errata #5 requires, followed by the TX
+    /// buffer
+    fn default() -> Self {
+        Layout::with_rx_size(INIT_RX_BUFFER_SIZE)
+    }
 }
 
-impl<Spi, Ncs, Int, Reset> InnerEnc28j60<Spi, Ncs, Int, Reset>
-where
-    Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8>,
-    Ncs: OutputPin,
-    Int: enc28j60::IntPin,
-    Reset: enc28j60::ResetPin,
-{
-    fn new(device: Enc28j60<Spi, Ncs, Int, Reset>) -> Self {
-        InnerEnc28j60 {
-            device: RefCell::new(device),
-            buffer: RefCell::new([0; BUFFER_SIZE]),
+/// Inter-packet gaps, programmed into MABBIPG and MAIPGL/MAIPGH
+#[derive(Clone, Copy)]
+struct InterPacketGaps {
+    back_to_back: u8,
+    non_back_to_back_low: u8,
+    non_back_to_back_high: u8,
+}
+
+impl InterPacketGaps {
+    /// Gaps recommended by the datasheet, MAIPGH is only used in half duplex
+    fn recommended(full_duplex: bool) -> Self {
+        if full_duplex {
+            InterPacketGaps {
+                back_to_back: 0x15,
+                non_back_to_back_low: 0x12,
+                non_back_to_back_high: 0x00,
+            }
+        } else {
+            InterPacketGaps {
+                back_to_back: 0x12,
+                non_back_to_back_low: 0x12,
+                non_back_to_back_high: 0x0C,
+            }
         }
     }
 
-    fn lock(&self) -> Option<SharedBuffer<Spi, Ncs, Int, Reset>> {
-        let device = self.device.try_borrow_mut().ok();
-        let buffer = self.buffer.try_borrow_mut().ok();
+    fn write<D>(self, device: &mut D) -> Result<()>
+    where
+        D: Registers,
+    {
+        device.write(Register::MABBIPG, self.back_to_back)?;
+        device.write(Register::MAIPGL, self.non_back_to_back_low)?;
+        device.write(Register::MAIPGH, self.non_back_to_back_high)
+    }
+}
+
+/// Receive filter configuration, programmed into ERXFCON
+#[derive(Clone, Copy, Default)]
+struct RxFilter {
+    promiscuous: bool,
+    multicast: bool,
+    reject_broadcast: bool,
+    hash_table: [u8; 8],
+}
+
+impl RxFilter {
+    fn erxfcon(self) -> u8 {
+        if self.promiscuous {
+            // With every filter disabled all frames are accepted, only those with a bad CRC are dropped
+            return ERXFCON_CRCEN;
+        }
 
-        if let Some(device) = device {
-            if let Some(buffer) = buffer {
-                return Some(SharedBuffer::new(device, buffer));
-            }
+        let mut erxfcon = ERXFCON_UCEN | ERXFCON_CRCEN;
+        if !self.reject_broadcast {
+            erxfcon |= ERXFCON_BCEN;
+        }
+        if self.multicast {
+            erxfcon |= ERXFCON_MCEN;
         }
+        if self.hash_table != [0; 8] {
+            erxfcon |= ERXFCON_HTEN;
+        }
+        erxfcon
+    }
+
+    fn write<D>(self, device: &mut D) -> Result<()>
+    where
+        D: Registers,
+    {
+        device.write_hash_table(self.hash_table)?;
+        device.write(Register::ERXFCON, self.erxfcon())
+    }
+}
+
+/// Hash table with the entries for the given destination addresses set
+fn hash_table(addresses: &[[u8; 6]]) -> [u8; 8] {
+    addresses.iter().fold([0; 8], |mut table, address| {
+        let entry = hash_table_entry(address);
+        table[usize::from(entry >> 3)] |= 1 << (entry & 0b111);
+        table
+    })
+}
+
+/// Hash table entry for a destination address, bits 28:23 of its CRC-32
+fn hash_table_entry(address: &[u8; 6]) -> u8 {
+    let crc = address.iter().fold(0xFFFF_FFFF_u32, |crc, byte| {
+        (0..8).fold(crc, |crc, bit| {
+            let feedback = (crc >> 31) ^ u32::from((byte >> bit) & 1);
+            let crc = crc << 1;
+            if feedback != 0 {
+                crc ^ 0x04C1_1DB7
+            } else {
+                crc
+            }
+        })
+    });
+
+    ((crc >> 23) & 0x3F) as u8
+}
+
+/// Whether the frame contains a wake-on-LAN magic packet for `mac`
+fn is_magic_packet(frame: &[u8], mac: &[u8; 6]) -> bool {
+    frame
+        .windows(mac.len() * (1 + MAGIC_PACKET_REPEATS))
+        .any(|window| {
+            let (sync, repeats) = window.split_at(mac.len());
+            sync.iter().all(|&byte| byte == 0xFF)
+                && repeats.chunks_exact(mac.len()).all(|repeat| repeat == mac)
+        })
+}
 
-        None
+/// Insert an 802.1Q tag after the addresses of the `len` byte frame at the start of `buffer`,
+/// returning the length of the tagged frame
+///
+/// `buffer` must have room for the tag. Frames too short to hold the addresses are left as is.
+fn insert_vlan_tag(buffer: &mut [u8], len: usize, vid: u16) -> usize {
+    if len < 12 {
+        return len;
     }
 
-    fn send(&self, mut buffer: SharedBuffer<Spi, Ncs, Int, Reset>) -> Result<()> {
-        match buffer.device.transmit(buffer.buffer.as_slice()) {
-            Ok(_) => Ok(()),
-            Err(_) => Err(Error::Illegal),
+    buffer.copy_within(12..len, 12 + VLAN_TAG_SIZE);
+    buffer[12..14].copy_from_slice(&VLAN_TPID.to_be_bytes());
+    buffer[14..16].copy_from_slice(&vid.to_be_bytes());
+    len + VLAN_TAG_SIZE
+}
+
+/// Frame buffer, either embedded in the wrapper or supplied through [SmolEnc28j60::with_buffer]
+///
+/// Embedded buffers are aligned so the frames handed to smoltcp start on a word boundary, external
+/// buffers are used as supplied.
+enum FrameBuffer<const N: usize> {
+    Embedded(AlignedBuffer<N>),
+    External(&'static mut [u8]),
+}
+
+/// Storage of an embedded frame buffer, aligned to 4 bytes
+///
+/// smoltcp reads multi-byte header fields out of the frame, which is slow when unaligned and
+/// faults on cores without unaligned access support such as the Cortex-M0. `repr(C)` keeps the
+/// array at offset 0, so the frames handed to the tokens start at the alignment of the struct.
+#[repr(C, align(4))]
+struct AlignedBuffer<const N: usize>([u8; N]);
+
+/// Alignment of the frames handed to the tokens, see [AlignedBuffer]
+const FRAME_ALIGN: usize = 4;
+
+impl<const N: usize> AlignedBuffer<N> {
+    /// Empty buffer, failing to compile for any `N` the buffer isn't aligned for
+    const ZEROED: Self = {
+        assert!(core::mem::align_of::<Self>() >= FRAME_ALIGN);
+        AlignedBuffer([0; N])
+    };
+}
+
+impl<const N: usize> Deref for FrameBuffer<N> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            FrameBuffer::Embedded(buffer) => &buffer.0,
+            FrameBuffer::External(buffer) => buffer,
         }
     }
+}
 
-    fn receive(&self, buffer: &mut SharedBuffer<Spi, Ncs, Int, Reset>) -> Result<()> {
-        buffer
-            .device
-            .receive(buffer.buffer.as_mut_slice())
-            .map(|_| ())
-            .map_err(|_| Error::Illegal)
+impl<const N: usize> DerefMut for FrameBuffer<N> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        match self {
+            FrameBuffer::Embedded(buffer) => &mut buffer.0,
+            FrameBuffer::External(buffer) => buffer,
+        }
     }
 }
 
-struct SharedBuffer<'a, Spi, Ncs, Int, Reset>
+/// Byte ring that received frames can be read straight into, see
+/// [SmolEnc28j60::receive_to_ring]
+pub trait RingWriter {
+    /// Bytes that can be written, including past the wrap around of the ring
+    fn free(&self) -> usize;
+
+    /// Contiguous free space at the write end of the ring, only empty if [RingWriter::free] is 0
+    fn window(&mut self) -> &mut [u8];
+
+    /// Mark the first `len` bytes of [RingWriter::window] as written
+    fn commit(&mut self, len: usize);
+}
+
+/// Writer streaming a frame into the transmit buffer of the ENC28J60, see
+/// [SmolEnc28j60::transmit_with]
+pub struct EncWriter<'a, Spi, Ncs, Int, Reset>
 where
     Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8>,
     Ncs: OutputPin,
     Int: enc28j60::IntPin,
     Reset: enc28j60::ResetPin,
 {
-    device: RefMut<'a, Enc28j60<Spi, Ncs, Int, Reset>>,
-    buffer: RefMut<'a, [u8; BUFFER_SIZE]>,
+    device: &'a mut BankedEnc28j60<Spi, Ncs, Int, Reset>,
+    written: usize,
+    error: Option<Error>,
 }
 
-impl<'a, Spi, Ncs, Int, Reset> SharedBuffer<'a, Spi, Ncs, Int, Reset>
+impl<'a, Spi, Ncs, Int, Reset> EncWriter<'a, Spi, Ncs, Int, Reset>
 where
     Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8>,
     Ncs: OutputPin,
     Int: enc28j60::IntPin,
     Reset: enc28j60::ResetPin,
 {
-    fn new(
-        device: RefMut<'a, Enc28j60<Spi, Ncs, Int, Reset>>,
-        buffer: RefMut<'a, [u8; BUFFER_SIZE]>,
-    ) -> Self {
-        SharedBuffer { device, buffer }
+    /// Append `bytes` to the frame
+    ///
+    /// [Error::FrameTooLong] is returned and nothing written if the frame would grow past
+    /// `1518 - 4` bytes.
+    pub fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        if self.written + bytes.len() > BUFFER_SIZE {
+            return Err(Error::FrameTooLong);
+        }
+
+        match self.device.write_buffer(bytes) {
+            Ok(()) => {
+                self.written += bytes.len();
+                Ok(())
+            }
+            Err(error) => {
+                self.error = Some(error);
+                Err(error)
+            }
+        }
+    }
+
+    /// Bytes written so far
+    pub fn len(&self) -> usize {
+        self.written
+    }
+
+    /// Whether nothing has been written yet
+    pub fn is_empty(&self) -> bool {
+        self.written == 0
     }
 }
 
+/// RX and TX halves returned by [SmolEnc28j60::split]
+type Halves<'a, Spi, Ncs, Int, Reset, const N: usize> = (
+    RxHalf<'a, Spi, Ncs, Int, Reset, N>,
+    TxHalf<'a, Spi, Ncs, Int, Reset, N>,
+);
+
+/// RX token along with the TX token smoltcp replies through
+type Tokens<'a, Spi, Ncs, Int, Reset, const N: usize> = (
+    RxToken<'a, Spi, Ncs, Int, Reset, N>,
+    TxToken<'a, Spi, Ncs, Int, Reset, N>,
+);
+
 /// RxToken for enc28j60
-pub struct RxToken<'a, Spi, Ncs, Int, Reset>
+pub struct RxToken<'a, Spi, Ncs, Int, Reset, const N: usize = BUFFER_SIZE>
+where
+    Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8>,
+    Ncs: OutputPin,
+    Int: enc28j60::IntPin,
+    Reset: enc28j60::ResetPin,
+{
+    lower: &'a InnerEnc28j60<Spi, Ncs, Int, Reset, N>,
+    /// Buffer taken from the pool set through [SmolEnc28j60::set_rx_pool]
+    #[cfg(feature = "heapless")]
+    buffer: Cell<Option<&'static mut [u8]>>,
+}
+
+/// Return the buffer of a token that was never consumed to the pool
+#[cfg(feature = "heapless")]
+impl<'a, Spi, Ncs, Int, Reset, const N: usize> Drop for RxToken<'a, Spi, Ncs, Int, Reset, N>
 where
     Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8>,
     Ncs: OutputPin,
     Int: enc28j60::IntPin,
     Reset: enc28j60::ResetPin,
 {
-    lower: &'a InnerEnc28j60<Spi, Ncs, Int, Reset>,
+    fn drop(&mut self) {
+        if let Some(buffer) = self.buffer.get_mut().take() {
+            self.lower.release(buffer);
+        }
+    }
 }
 
-impl<'a, Spi, Ncs, Int, Reset> phy::RxToken for RxToken<'a, Spi, Ncs, Int, Reset>
+impl<'a, Spi, Ncs, Int, Reset, const N: usize> phy::RxToken for RxToken<'a, Spi, Ncs, Int, Reset, N>
 where
     Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8>,
     Ncs: OutputPin,
     Int: enc28j60::IntPin,
     Reset: enc28j60::ResetPin,
 {
-    fn consume<R, F>(self, _timestamp: smoltcp::time::Instant, f: F) -> smoltcp::Result<R>
+    fn consume<R, F>(self, timestamp: Instant, f: F) -> smoltcp::Result<R>
     where
         F: FnOnce(&mut [u8]) -> smoltcp::Result<R>,
     {
-        let buffer = self.lower.lock();
-        match buffer {
-            None => Err(smoltcp::Error::Exhausted),
-            Some(mut buffer) => {
-                self.lower.receive(&mut buffer)?;
-                f(buffer.buffer.as_mut_slice())
+        #[cfg(feature = "heapless")]
+        if let Ok(mut ring) = self.lower.ring.try_borrow_mut() {
+            if let Some(ring) = ring.as_mut() {
+                if let Some(frame) = ring.front_mut() {
+                    self.lower.last_rx_instant.set(Some(timestamp));
+                    let result = self.lower.consuming(|| f(&mut frame[..]));
+                    if self.lower.zero_on_receive {
+                        frame.clear();
+                        let _ = frame.resize_default(BUFFER_SIZE);
+                    }
+                    ring.discard_front();
+                    return result;
+                }
             }
         }
+
+        #[cfg(feature = "heapless")]
+        if let Some(buffer) = self.buffer.take() {
+            let result = self.lower.consume_into(buffer, timestamp, f);
+            self.lower.release(buffer);
+            return result;
+        }
+
+        // The device is only held while the frame is read out, so a TX token can be consumed from
+        // within `f`, such as to reply to the frame
+        let mut buffer = self
+            .lower
+            .rx_buffer
+            .try_borrow_mut()
+            .map_err(|_| self.lower.busy("RX"))?;
+        self.lower.consume_into(&mut buffer, timestamp, f)
     }
 }
 
 /// TxToken for enc28j60
-pub struct TxToken<'a, Spi, Ncs, Int, Reset>
+///
+/// Consuming the token with a length of 0 calls the closure with an empty buffer and transmits
+/// nothing, so no runt frame is put on the wire.
+pub struct TxToken<'a, Spi, Ncs, Int, Reset, const N: usize = BUFFER_SIZE>
 where
     Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8>,
     Ncs: OutputPin,
     Int: enc28j60::IntPin,
     Reset: enc28j60::ResetPin,
 {
-    lower: &'a InnerEnc28j60<Spi, Ncs, Int, Reset>,
+    lower: &'a InnerEnc28j60<Spi, Ncs, Int, Reset, N>,
 }
 
-impl<'a, Spi, Ncs, Int, Reset> phy::TxToken for TxToken<'a, Spi, Ncs, Int, Reset>
+impl<'a, Spi, Ncs, Int, Reset, const N: usize> phy::TxToken for TxToken<'a, Spi, Ncs, Int, Reset, N>
 where
     Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8>,
     Ncs: OutputPin,
     Int: enc28j60::IntPin,
     Reset: enc28j60::ResetPin,
 {
-    fn consume<R, F>(
-        self,
-        _timestamp: smoltcp::time::Instant,
-        len: usize,
-        f: F,
-    ) -> smoltcp::Result<R>
+    fn consume<R, F>(self, timestamp: Instant, len: usize, f: F) -> smoltcp::Result<R>
     where
         F: FnOnce(&mut [u8]) -> smoltcp::Result<R>,
     {
-        if len > BUFFER_SIZE {
-            return Err(smoltcp::Error::Exhausted);
+        if len == 0 {
+            trace!("skipping transmit of an empty frame");
+            return f(&mut []);
         }
 
-        let buffer = self.lower.lock();
-        match buffer {
-            None => Err(smoltcp::Error::Exhausted),
-            Some(mut buffer) => {
-                let result = f(buffer.buffer.as_mut_slice());
-                self.lower.send(buffer)?;
-                result
-            }
+        // Exhausted would have smoltcp retry a frame that can never be sent
+        if len > self.lower.max_frame_len() {
+            warn!("frame of {} bytes is too long to transmit", len);
+            return Err(self.lower.fail(Error::FrameTooLong).into());
         }
+
+        let mut buffer = self
+            .lower
+            .tx_buffer
+            .try_borrow_mut()
+            .map_err(|_| self.lower.busy("TX"))?;
+        let result = self.lower.consuming(|| f(&mut buffer[..len]));
+        let len = match self.lower.vlan {
+            Some(vid) => insert_vlan_tag(&mut buffer, len, vid),
+            None => len,
+        };
+        self.lower.send(&buffer[..len])?;
+        self.lower.last_tx_instant.set(Some(timestamp));
+        result
     }
 }
 
-enum Error {
+/// Errors raised by the ENC28J60
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
     /// An operation is not permitted in the current state.
     Illegal,
+    /// An SPI transfer with the ENC28J60 failed
+    SpiTransfer,
+    /// The ENC28J60 aborted the transmission (ESTAT.TXABRT)
+    TransmitAbort,
+    /// The transmission was aborted by a late collision (ESTAT.LATECOL)
+    LateCollision,
+    /// The receive buffer overflowed and frames were lost (EIR.RXERIF)
+    BufferOverflow,
+    /// Waiting on the INT pin failed
+    InterruptPin,
+    /// The ENC28J60 flagged the received frame as bad, such as for a CRC error, or its status
+    /// vector claimed a length longer than a frame can be, and it was dropped. Also returned for
+    /// frames dropped by [SmolEnc28j60::set_drop_bad_checksums]
+    InvalidFrame,
+    /// The ENC28J60 didn't finish transmitting in time and the transmission was aborted
+    TransmitTimeout,
+    /// The frame is longer than the ENC28J60, or the frame buffer, can transmit
+    FrameTooLong,
+    /// The frame buffer is held by another token, try again once it has been consumed
+    ///
+    /// This is transient contention rather than a lack of buffer space, but is reported to smoltcp
+    /// as [smoltcp::Error::Exhausted], which has no better match. Occurrences are counted in
+    /// [Stats::buffer_busy].
+    BufferBusy,
+    /// Every waiting frame was dropped by the software receive filter, see
+    /// [SmolEnc28j60::set_rx_filter]
+    ///
+    /// This is reported to smoltcp as [smoltcp::Error::Exhausted], as no frame was left to receive.
+    Filtered,
+    /// No received frame was waiting, such as when the frame a token was handed out for was read
+    /// through another token of a [SharedSmolEnc28j60]
+    ///
+    /// This is reported to smoltcp as [smoltcp::Error::Exhausted].
+    NoFrame,
+    /// The ENC28J60 oscillator didn't start in time (ESTAT.CLKRDY)
+    NotReady,
+    /// The PHY didn't finish an access through the MII registers in time (MISTAT.BUSY)
+    PhyTimeout,
+    /// Writing to the output of [SmolEnc28j60::dump_registers] failed
+    Format,
+    /// The RX and TX buffers would overlap or not fit in the buffer memory, see
+    /// [SmolEnc28j60::set_buffer_split] and [SmolEnc28j60::set_tx_start], or there is no room left
+    /// for [SmolEnc28j60::prepare_frame]
+    InvalidLayout,
+}
+
+impl Error {
+    /// Whether the SPI bus failed, rather than the ENC28J60 reporting an error
+    ///
+    /// Bus errors usually come from wiring or clock problems and leave the ENC28J60 in an unknown
+    /// state, so warrant a [SmolEnc28j60::reset]. The errors the ENC28J60 reports, such as
+    /// [Error::TransmitAbort] or [Error::InvalidFrame], only affect a single frame.
+    pub fn is_bus_error(self) -> bool {
+        self == Error::SpiTransfer
+    }
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            Error::Illegal => "operation not permitted in the current state",
+            Error::SpiTransfer => "SPI transfer failed",
+            Error::TransmitAbort => "transmit aborted",
+            Error::LateCollision => "transmit aborted: late collision",
+            Error::BufferOverflow => "receive buffer overflowed",
+            Error::InterruptPin => "waiting on the INT pin failed",
+            Error::InvalidFrame => "invalid frame dropped",
+            Error::TransmitTimeout => "transmit timed out",
+            Error::FrameTooLong => "frame too long",
+            Error::BufferBusy => "device busy",
+            Error::Filtered => "every waiting frame was filtered",
+            Error::NoFrame => "no frame waiting",
+            Error::NotReady => "oscillator not ready",
+            Error::PhyTimeout => "PHY access timed out",
+            Error::Format => "formatting the output failed",
+            Error::InvalidLayout => "invalid RX and TX buffer layout",
+        })
+    }
 }
 
 #[cfg(feature = "std")]
 impl std::error::Error for Error {}
 
-type Result<T> = core::result::Result<T, Error>;
+/// Silicon revision of the ENC28J60, see [SmolEnc28j60::revision_enum]
+///
+/// The errata sheet lists the issues the wrapper works around, #5 (RX buffer start) and #12
+/// (transmit logic stall), for every known revision, so the workarounds are applied whatever the
+/// revision.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Revision {
+    /// Rev. B1, EREVID 0x02
+    B1,
+    /// Rev. B4, EREVID 0x04
+    B4,
+    /// Rev. B5, EREVID 0x05
+    B5,
+    /// Rev. B7, EREVID 0x06
+    B7,
+    /// A revision not known to the wrapper
+    Unknown(u8),
+}
+
+impl Revision {
+    fn from_erevid(erevid: u8) -> Self {
+        match erevid {
+            0x02 => Revision::B1,
+            0x04 => Revision::B4,
+            0x05 => Revision::B5,
+            0x06 => Revision::B7,
+            erevid => Revision::Unknown(erevid),
+        }
+    }
+}
+
+/// Error returned by [SmolEnc28j60::try_new]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RevisionError {
+    /// EREVID could not be read over SPI
+    Spi,
+    /// EREVID holds an unknown silicon revision, `0x00` usually means the device isn't responding
+    Unknown(u8),
+    /// The oscillator didn't start in time (ESTAT.CLKRDY), so the device can't be used yet
+    NotReady,
+    /// A test pattern read back different from what was written, the SPI bus is likely in the
+    /// wrong mode (the ENC28J60 needs mode 0) or clocked too fast
+    SpiConfig,
+}
+
+/// Result type for fallible ENC28J60 operations
+pub type Result<T> = core::result::Result<T, Error>;
 
 impl From<Error> for smoltcp::Error {
-    fn from(_: Error) -> Self {
-        smoltcp::Error::Illegal
+    fn from(error: Error) -> Self {
+        match error {
+            Error::BufferBusy | Error::Filtered | Error::NoFrame => smoltcp::Error::Exhausted,
+            error => {
+                warn!("{:?} reported to smoltcp as Illegal", error);
+                smoltcp::Error::Illegal
+            }
+        }
     }
 }
@@ -0,0 +1,23 @@
+//! Pool of RX buffers, letting several RX tokens be handed out at once
+
+use heapless::Vec;
+
+/// Free list of buffers of any depth
+pub(crate) trait BufferPool {
+    /// Take a free buffer for a token
+    fn take(&mut self) -> Option<&'static mut [u8]>;
+
+    /// Return the buffer of a token once it is consumed or dropped
+    fn give(&mut self, buffer: &'static mut [u8]);
+}
+
+impl<const P: usize> BufferPool for Vec<&'static mut [u8], P> {
+    fn take(&mut self) -> Option<&'static mut [u8]> {
+        self.pop()
+    }
+
+    fn give(&mut self, buffer: &'static mut [u8]) {
+        // Only buffers taken from the pool are given back, so there is always room
+        let _ = self.push(buffer);
+    }
+}
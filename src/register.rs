@@ -0,0 +1,809 @@
+//! ENC28J60 register map and bank-aware register access
+
+use core::ops::{Deref, DerefMut};
+
+use embedded_hal::blocking;
+use embedded_hal::digital::v2::OutputPin;
+use enc28j60::Enc28j60;
+
+#[cfg(feature = "metrics")]
+use crate::SpiStats;
+use crate::{Error, Result};
+
+/// Register bank, selected through ECON1.BSEL
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Bank {
+    /// Registers mapped into every bank (EIE, EIR, ESTAT, ECON2, ECON1)
+    Common,
+    /// Bank 0
+    Bank0,
+    /// Bank 1
+    Bank1,
+    /// Bank 2
+    Bank2,
+    /// Bank 3
+    Bank3,
+}
+
+impl Bank {
+    fn bsel(self) -> Option<u8> {
+        match self {
+            Bank::Common => None,
+            Bank::Bank0 => Some(0b00),
+            Bank::Bank1 => Some(0b01),
+            Bank::Bank2 => Some(0b10),
+            Bank::Bank3 => Some(0b11),
+        }
+    }
+}
+
+/// Control register location
+#[derive(Clone, Copy)]
+pub struct Register {
+    bank: Bank,
+    address: u8,
+    /// MAC and MII registers shift out a dummy byte before the data on read
+    mac_mii: bool,
+}
+
+impl Register {
+    /// ETH control register at `address` in `bank`
+    pub const fn eth(bank: Bank, address: u8) -> Self {
+        Register {
+            bank,
+            address,
+            mac_mii: false,
+        }
+    }
+
+    /// MAC or MII control register at `address` in `bank`, which are read differently from the ETH
+    /// registers
+    pub const fn mac(bank: Bank, address: u8) -> Self {
+        Register {
+            bank,
+            address,
+            mac_mii: true,
+        }
+    }
+
+    pub(crate) const EIE: Register = Register::eth(Bank::Common, 0x1B);
+    pub(crate) const EIR: Register = Register::eth(Bank::Common, 0x1C);
+    pub(crate) const ESTAT: Register = Register::eth(Bank::Common, 0x1D);
+    pub(crate) const ECON2: Register = Register::eth(Bank::Common, 0x1E);
+    pub(crate) const ECON1: Register = Register::eth(Bank::Common, 0x1F);
+
+    const ERDPTL: Register = Register::eth(Bank::Bank0, 0x00);
+    const ERDPTH: Register = Register::eth(Bank::Bank0, 0x01);
+    const EWRPTL: Register = Register::eth(Bank::Bank0, 0x02);
+    const EWRPTH: Register = Register::eth(Bank::Bank0, 0x03);
+    const ETXSTL: Register = Register::eth(Bank::Bank0, 0x04);
+    const ETXSTH: Register = Register::eth(Bank::Bank0, 0x05);
+    const ETXNDL: Register = Register::eth(Bank::Bank0, 0x06);
+    const ETXNDH: Register = Register::eth(Bank::Bank0, 0x07);
+    const ERXSTL: Register = Register::eth(Bank::Bank0, 0x08);
+    const ERXSTH: Register = Register::eth(Bank::Bank0, 0x09);
+    const ERXNDL: Register = Register::eth(Bank::Bank0, 0x0A);
+    const ERXNDH: Register = Register::eth(Bank::Bank0, 0x0B);
+    const ERXRDPTL: Register = Register::eth(Bank::Bank0, 0x0C);
+    const ERXRDPTH: Register = Register::eth(Bank::Bank0, 0x0D);
+    const ERXWRPTL: Register = Register::eth(Bank::Bank0, 0x0E);
+    const ERXWRPTH: Register = Register::eth(Bank::Bank0, 0x0F);
+
+    const EHT0: Register = Register::eth(Bank::Bank1, 0x00);
+    const EHT1: Register = Register::eth(Bank::Bank1, 0x01);
+    const EHT2: Register = Register::eth(Bank::Bank1, 0x02);
+    const EHT3: Register = Register::eth(Bank::Bank1, 0x03);
+    const EHT4: Register = Register::eth(Bank::Bank1, 0x04);
+    const EHT5: Register = Register::eth(Bank::Bank1, 0x05);
+    const EHT6: Register = Register::eth(Bank::Bank1, 0x06);
+    const EHT7: Register = Register::eth(Bank::Bank1, 0x07);
+    pub(crate) const ERXFCON: Register = Register::eth(Bank::Bank1, 0x18);
+    pub(crate) const EPKTCNT: Register = Register::eth(Bank::Bank1, 0x19);
+
+    pub(crate) const MACON1: Register = Register::mac(Bank::Bank2, 0x00);
+    pub(crate) const MACON3: Register = Register::mac(Bank::Bank2, 0x02);
+    pub(crate) const MACON4: Register = Register::mac(Bank::Bank2, 0x03);
+    pub(crate) const MABBIPG: Register = Register::mac(Bank::Bank2, 0x04);
+    pub(crate) const MAIPGL: Register = Register::mac(Bank::Bank2, 0x06);
+    pub(crate) const MAIPGH: Register = Register::mac(Bank::Bank2, 0x07);
+    pub(crate) const MAMXFLL: Register = Register::mac(Bank::Bank2, 0x0A);
+    pub(crate) const MAMXFLH: Register = Register::mac(Bank::Bank2, 0x0B);
+    const MICMD: Register = Register::mac(Bank::Bank2, 0x12);
+    const MIREGADR: Register = Register::mac(Bank::Bank2, 0x14);
+    const MIWRL: Register = Register::mac(Bank::Bank2, 0x16);
+    const MIWRH: Register = Register::mac(Bank::Bank2, 0x17);
+    const MIRDL: Register = Register::mac(Bank::Bank2, 0x18);
+    const MIRDH: Register = Register::mac(Bank::Bank2, 0x19);
+
+    const MAADR1: Register = Register::mac(Bank::Bank3, 0x04);
+    const MAADR2: Register = Register::mac(Bank::Bank3, 0x05);
+    const MAADR3: Register = Register::mac(Bank::Bank3, 0x02);
+    const MAADR4: Register = Register::mac(Bank::Bank3, 0x03);
+    const MAADR5: Register = Register::mac(Bank::Bank3, 0x00);
+    const MAADR6: Register = Register::mac(Bank::Bank3, 0x01);
+    const MISTAT: Register = Register::mac(Bank::Bank3, 0x0A);
+    pub(crate) const EREVID: Register = Register::eth(Bank::Bank3, 0x12);
+    pub(crate) const EFLOCON: Register = Register::eth(Bank::Bank3, 0x17);
+    pub(crate) const EPAUSL: Register = Register::eth(Bank::Bank3, 0x18);
+    pub(crate) const EPAUSH: Register = Register::eth(Bank::Bank3, 0x19);
+}
+
+/// PHY register address, accessed indirectly through the MII registers
+#[derive(Clone, Copy)]
+pub(crate) struct PhyRegister(u8);
+
+impl PhyRegister {
+    pub(crate) const PHCON1: PhyRegister = PhyRegister(0x00);
+    pub(crate) const PHSTAT1: PhyRegister = PhyRegister(0x01);
+    pub(crate) const PHCON2: PhyRegister = PhyRegister(0x10);
+    pub(crate) const PHSTAT2: PhyRegister = PhyRegister(0x11);
+    pub(crate) const PHIE: PhyRegister = PhyRegister(0x12);
+    pub(crate) const PHIR: PhyRegister = PhyRegister(0x13);
+    pub(crate) const PHLCON: PhyRegister = PhyRegister(0x14);
+}
+
+/// 16-bit buffer pointer split over a low and high register
+///
+/// The low byte must be written first, the pointer is updated when the high byte is written, so
+/// writing them the other way around leaves the pointer briefly at a mix of the old and new values,
+/// which for ERXRDPT frees RX buffer memory still holding frames. The two halves are only ever
+/// accessed together through [Registers::read_pointer] and [Registers::write_pointer], which keep
+/// to this order and convert from and to the little endian layout of the registers.
+#[derive(Clone, Copy)]
+pub(crate) struct Pointer {
+    low: Register,
+    high: Register,
+}
+
+impl Pointer {
+    /// Pause timer value (EPAUSL/EPAUSH), not a pointer but written the same way
+    pub(crate) const EPAUS: Pointer = Pointer {
+        low: Register::EPAUSL,
+        high: Register::EPAUSH,
+    };
+    pub(crate) const ERDPT: Pointer = Pointer {
+        low: Register::ERDPTL,
+        high: Register::ERDPTH,
+    };
+    pub(crate) const EWRPT: Pointer = Pointer {
+        low: Register::EWRPTL,
+        high: Register::EWRPTH,
+    };
+    pub(crate) const ETXST: Pointer = Pointer {
+        low: Register::ETXSTL,
+        high: Register::ETXSTH,
+    };
+    pub(crate) const ETXND: Pointer = Pointer {
+        low: Register::ETXNDL,
+        high: Register::ETXNDH,
+    };
+    pub(crate) const ERXST: Pointer = Pointer {
+        low: Register::ERXSTL,
+        high: Register::ERXSTH,
+    };
+    pub(crate) const ERXND: Pointer = Pointer {
+        low: Register::ERXNDL,
+        high: Register::ERXNDH,
+    };
+    pub(crate) const ERXRDPT: Pointer = Pointer {
+        low: Register::ERXRDPTL,
+        high: Register::ERXRDPTH,
+    };
+    pub(crate) const ERXWRPT: Pointer = Pointer {
+        low: Register::ERXWRPTL,
+        high: Register::ERXWRPTH,
+    };
+}
+
+/// ERXFCON.UCEN
+pub(crate) const ERXFCON_UCEN: u8 = 1 << 7;
+/// ERXFCON.CRCEN
+pub(crate) const ERXFCON_CRCEN: u8 = 1 << 5;
+/// ERXFCON.HTEN
+pub(crate) const ERXFCON_HTEN: u8 = 1 << 2;
+/// ERXFCON.MCEN
+pub(crate) const ERXFCON_MCEN: u8 = 1 << 1;
+/// ERXFCON.BCEN
+pub(crate) const ERXFCON_BCEN: u8 = 1 << 0;
+/// ESTAT.CLKRDY
+const ESTAT_CLKRDY: u8 = 1 << 0;
+/// Values written and read back by [Registers::spi_handshake]
+const SPI_HANDSHAKE_PATTERNS: [u8; 2] = [0x55, 0xAA];
+/// EIE.INTIE, enable the INT pin
+pub(crate) const EIE_INTIE: u8 = 1 << 7;
+/// EIE.PKTIE
+#[cfg(feature = "async")]
+pub(crate) const EIE_PKTIE: u8 = 1 << 6;
+/// EIE.LINKIE
+pub(crate) const EIE_LINKIE: u8 = 1 << 4;
+/// EIR.PKTIF
+pub(crate) const EIR_PKTIF: u8 = 1 << 6;
+/// EIR.DMAIF
+pub(crate) const EIR_DMAIF: u8 = 1 << 5;
+/// EIR.LINKIF
+pub(crate) const EIR_LINKIF: u8 = 1 << 4;
+/// EIR.TXIF
+pub(crate) const EIR_TXIF: u8 = 1 << 3;
+/// EIR.TXERIF
+pub(crate) const EIR_TXERIF: u8 = 1 << 1;
+/// EIR.RXERIF
+pub(crate) const EIR_RXERIF: u8 = 1 << 0;
+/// ESTAT.LATECOL
+const ESTAT_LATECOL: u8 = 1 << 4;
+/// ESTAT.RXBUSY
+const ESTAT_RXBUSY: u8 = 1 << 2;
+/// ESTAT.TXABRT
+const ESTAT_TXABRT: u8 = 1 << 1;
+/// ECON1.TXRST
+const ECON1_TXRST: u8 = 1 << 7;
+/// ECON1.RXRST
+const ECON1_RXRST: u8 = 1 << 6;
+/// ECON1.TXRTS
+pub(crate) const ECON1_TXRTS: u8 = 1 << 3;
+/// ECON1.RXEN
+pub(crate) const ECON1_RXEN: u8 = 1 << 2;
+/// ECON2.PKTDEC
+pub(crate) const ECON2_PKTDEC: u8 = 1 << 6;
+/// ECON2.PWRSV
+pub(crate) const ECON2_PWRSV: u8 = 1 << 5;
+/// ECON2.VRPS, run the voltage regulator in low current mode while powered down
+pub(crate) const ECON2_VRPS: u8 = 1 << 3;
+/// EFLOCON.FCEN1:FCEN0 in full duplex, send pause frames periodically
+pub(crate) const EFLOCON_FCEN_PAUSE: u8 = 0b11;
+/// EFLOCON.FCEN1:FCEN0 in full duplex, send one pause frame with a pause time of 0 to release the
+/// link partner, then disable flow control
+pub(crate) const EFLOCON_FCEN_RELEASE: u8 = 0b10;
+/// MACON1.MARXEN
+pub(crate) const MACON1_MARXEN: u8 = 1 << 0;
+/// MACON3.PADCFG0, pad short frames to 60 bytes and append a CRC
+pub(crate) const MACON3_PADCFG0: u8 = 1 << 5;
+/// MACON3.TXCRCEN
+pub(crate) const MACON3_TXCRCEN: u8 = 1 << 4;
+/// MACON3.FRMLNEN
+pub(crate) const MACON3_FRMLNEN: u8 = 1 << 1;
+/// MACON3.FULDPX
+pub(crate) const MACON3_FULDPX: u8 = 1 << 0;
+/// MACON4.DEFER
+pub(crate) const MACON4_DEFER: u8 = 1 << 6;
+/// ECON1.BSEL1:BSEL0
+const ECON1_BSEL: u8 = 0b11;
+/// MICMD.MIIRD
+const MICMD_MIIRD: u8 = 1 << 0;
+/// MISTAT.BUSY
+const MISTAT_BUSY: u8 = 1 << 0;
+/// PHSTAT2.LSTAT
+const PHSTAT2_LSTAT: u16 = 1 << 10;
+/// PHSTAT2.DPXSTAT
+const PHSTAT2_DPXSTAT: u16 = 1 << 9;
+/// PHCON1.PLOOPBK
+pub(crate) const PHCON1_PLOOPBK: u16 = 1 << 14;
+/// PHCON1.PDPXMD
+pub(crate) const PHCON1_PDPXMD: u16 = 1 << 8;
+/// PHCON2.HDLDIS
+pub(crate) const PHCON2_HDLDIS: u16 = 1 << 8;
+/// PHIE.PLNKIE
+pub(crate) const PHIE_PLNKIE: u16 = 1 << 4;
+/// PHIE.PGEIE, forward PHY interrupts to EIR.LINKIF
+pub(crate) const PHIE_PGEIE: u16 = 1 << 1;
+/// PHIR.PLNKIF
+pub(crate) const PHIR_PLNKIF: u16 = 1 << 4;
+/// PHLCON bits 13:12, reserved and must be written as 1
+pub(crate) const PHLCON_RESERVED: u16 = 0b11 << 12;
+/// PHLCON.STRCH, stretch LED events so short ones are visible
+pub(crate) const PHLCON_STRCH: u16 = 1 << 1;
+
+/// Number of MISTAT polls before a PHY operation is considered stuck
+///
+/// A PHY operation takes 10.24 µs, which is only a handful of polls on any reasonable SPI clock
+const MII_BUSY_POLLS: usize = 1000;
+
+/// Size of the transmit status vector
+pub(crate) const TSV_SIZE: usize = 7;
+
+/// Number of ESTAT polls while waiting for the oscillator to start after a reset
+pub(crate) const CLKRDY_POLLS: usize = 1000;
+
+/// Number of ESTAT and ECON1 polls while waiting for a frame in progress to finish
+///
+/// A full frame takes 1.2 ms on the wire
+const IDLE_POLLS: usize = 10_000;
+
+/// Bank-aware access to the control registers of an ENC28J60
+pub(crate) trait Registers {
+    /// Read a control register
+    fn read(&mut self, register: Register) -> Result<u8>;
+
+    /// Write a control register
+    fn write(&mut self, register: Register, value: u8) -> Result<()>;
+
+    /// Set bits in an ETH control register (BFS)
+    fn set_bits(&mut self, register: Register, mask: u8) -> Result<()>;
+
+    /// Clear bits in an ETH control register (BFC)
+    fn clear_bits(&mut self, register: Register, mask: u8) -> Result<()>;
+
+    /// Read buffer memory from ERDPT onwards
+    fn read_buffer(&mut self, buffer: &mut [u8]) -> Result<()>;
+
+    /// Write buffer memory from EWRPT onwards
+    fn write_buffer(&mut self, buffer: &[u8]) -> Result<()>;
+
+    /// Read buffer memory in transfers of at most `chunk` bytes, or in one transfer if `chunk` is 0
+    fn read_buffer_chunked(&mut self, buffer: &mut [u8], chunk: usize) -> Result<()> {
+        if chunk == 0 {
+            return self.read_buffer(buffer);
+        }
+
+        buffer
+            .chunks_mut(chunk)
+            .try_for_each(|part| self.read_buffer(part))
+    }
+
+    /// Write buffer memory in transfers of at most `chunk` bytes, or in one transfer if `chunk` is
+    /// 0
+    fn write_buffer_chunked(&mut self, buffer: &[u8], chunk: usize) -> Result<()> {
+        if chunk == 0 {
+            return self.write_buffer(buffer);
+        }
+
+        buffer
+            .chunks(chunk)
+            .try_for_each(|part| self.write_buffer(part))
+    }
+
+    /// Read a 16-bit pointer, low byte first
+    fn read_pointer(&mut self, pointer: Pointer) -> Result<u16> {
+        let low = self.read(pointer.low)?;
+        let high = self.read(pointer.high)?;
+        Ok(u16::from_le_bytes([low, high]))
+    }
+
+    /// Write a 16-bit pointer, low byte first
+    fn write_pointer(&mut self, pointer: Pointer, value: u16) -> Result<()> {
+        let [low, high] = value.to_le_bytes();
+        self.write(pointer.low, low)?;
+        self.write(pointer.high, high)
+    }
+
+    /// Read a PHY register through MIREGADR/MICMD/MIRD
+    fn read_phy(&mut self, register: PhyRegister) -> Result<u16> {
+        self.write(Register::MIREGADR, register.0)?;
+        self.write(Register::MICMD, MICMD_MIIRD)?;
+        self.wait_mii()?;
+        self.write(Register::MICMD, 0)?;
+
+        let low = self.read(Register::MIRDL)?;
+        let high = self.read(Register::MIRDH)?;
+        Ok(u16::from_le_bytes([low, high]))
+    }
+
+    /// Write a PHY register through MIREGADR/MIWR
+    fn write_phy(&mut self, register: PhyRegister, value: u16) -> Result<()> {
+        let [low, high] = value.to_le_bytes();
+        self.write(Register::MIREGADR, register.0)?;
+        self.write(Register::MIWRL, low)?;
+        // Writing MIWRH starts the PHY write
+        self.write(Register::MIWRH, high)?;
+        self.wait_mii()
+    }
+
+    /// Wait for MISTAT.BUSY to clear, failing with [Error::PhyTimeout] if it doesn't
+    fn wait_mii(&mut self) -> Result<()> {
+        for _ in 0..MII_BUSY_POLLS {
+            if self.read(Register::MISTAT)? & MISTAT_BUSY == 0 {
+                return Ok(());
+            }
+        }
+
+        Err(Error::PhyTimeout)
+    }
+
+    /// Read the station MAC address from MAADR1..MAADR6
+    fn read_mac_address(&mut self) -> Result<[u8; 6]> {
+        Ok([
+            self.read(Register::MAADR1)?,
+            self.read(Register::MAADR2)?,
+            self.read(Register::MAADR3)?,
+            self.read(Register::MAADR4)?,
+            self.read(Register::MAADR5)?,
+            self.read(Register::MAADR6)?,
+        ])
+    }
+
+    /// Write the station MAC address to MAADR1..MAADR6
+    fn write_mac_address(&mut self, mac: [u8; 6]) -> Result<()> {
+        self.write(Register::MAADR1, mac[0])?;
+        self.write(Register::MAADR2, mac[1])?;
+        self.write(Register::MAADR3, mac[2])?;
+        self.write(Register::MAADR4, mac[3])?;
+        self.write(Register::MAADR5, mac[4])?;
+        self.write(Register::MAADR6, mac[5])
+    }
+
+    /// Write the receive hash table EHT0..EHT7
+    fn write_hash_table(&mut self, table: [u8; 8]) -> Result<()> {
+        self.write(Register::EHT0, table[0])?;
+        self.write(Register::EHT1, table[1])?;
+        self.write(Register::EHT2, table[2])?;
+        self.write(Register::EHT3, table[3])?;
+        self.write(Register::EHT4, table[4])?;
+        self.write(Register::EHT5, table[5])?;
+        self.write(Register::EHT6, table[6])?;
+        self.write(Register::EHT7, table[7])
+    }
+
+    /// Wait for ESTAT.CLKRDY to be set once the oscillator has started
+    fn wait_clock_ready(&mut self) -> Result<()> {
+        for _ in 0..CLKRDY_POLLS {
+            if self.read(Register::ESTAT)? & ESTAT_CLKRDY != 0 {
+                return Ok(());
+            }
+        }
+
+        Err(Error::NotReady)
+    }
+
+    /// Whether test patterns written to a register read back unchanged
+    ///
+    /// EPAUSL only matters while pause frames are sent, and is restored afterwards. The patterns
+    /// alternate every bit, so a bus sampling on the wrong clock edge, which shifts the data by a
+    /// bit, reads them back wrong.
+    fn spi_handshake(&mut self) -> Result<bool> {
+        let saved = self.read(Register::EPAUSL)?;
+        let mut matched = true;
+        for pattern in SPI_HANDSHAKE_PATTERNS {
+            self.write(Register::EPAUSL, pattern)?;
+            matched &= self.read(Register::EPAUSL)? == pattern;
+        }
+        self.write(Register::EPAUSL, saved)?;
+
+        Ok(matched)
+    }
+
+    /// Wait for any reception (ESTAT.RXBUSY) and transmission (ECON1.TXRTS) in progress to finish
+    fn wait_idle(&mut self) -> Result<()> {
+        for _ in 0..IDLE_POLLS {
+            if self.read(Register::ESTAT)? & ESTAT_RXBUSY == 0
+                && self.read(Register::ECON1)? & ECON1_TXRTS == 0
+            {
+                return Ok(());
+            }
+        }
+
+        Err(Error::Illegal)
+    }
+
+    /// Whether a frame is being transmitted (ECON1.TXRTS)
+    ///
+    /// TXRTS can be left set by a stalled transmit after an error (EIR.TXERIF), which isn't
+    /// counted as in progress.
+    fn transmit_in_progress(&mut self) -> Result<bool> {
+        let txrts = self.read(Register::ECON1)? & ECON1_TXRTS != 0;
+        Ok(txrts && self.read(Register::EIR)? & EIR_TXERIF == 0)
+    }
+
+    /// Reset the receive logic (ECON1.RXRST), reception must already be disabled
+    fn reset_receive(&mut self) -> Result<()> {
+        self.set_bits(Register::ECON1, ECON1_RXRST)?;
+        self.clear_bits(Register::ECON1, ECON1_RXRST)
+    }
+
+    /// Reset the transmit logic ahead of a transmission (ECON1.TXRST)
+    ///
+    /// Errata #12: the transmit logic can stall after a transmit error and must be reset before
+    /// transmitting again, it is simplest to do so before every transmission. [Error::Illegal] is
+    /// returned rather than aborting a frame that is still being transmitted.
+    fn reset_transmit(&mut self) -> Result<()> {
+        if self.transmit_in_progress()? {
+            return Err(Error::Illegal);
+        }
+
+        self.set_bits(Register::ECON1, ECON1_TXRST)?;
+        self.clear_bits(Register::ECON1, ECON1_TXRST | ECON1_TXRTS)?;
+        self.clear_bits(Register::EIR, EIR_TXERIF)
+    }
+
+    /// Point EWRPT at the transmit buffer at `tx_start` and write the control byte, so the frame
+    /// can be written after it
+    fn prepare_transmit(&mut self, tx_start: u16) -> Result<()> {
+        self.write_pointer(Pointer::EWRPT, tx_start)?;
+        // Per packet control byte, 0 to use the MACON3 settings
+        self.write_buffer(&[0])
+    }
+
+    /// Start transmitting the `len` byte frame written after [Registers::prepare_transmit]
+    /// (ECON1.TXRTS)
+    fn start_transmit(&mut self, tx_start: u16, len: usize) -> Result<()> {
+        self.write_pointer(Pointer::ETXST, tx_start)?;
+        self.write_pointer(Pointer::ETXND, tx_start.wrapping_add(len as u16))?;
+        self.clear_bits(Register::EIR, EIR_TXIF)?;
+        self.set_bits(Register::ECON1, ECON1_TXRTS)
+    }
+
+    /// Wait for ECON1.TXRTS to clear once the frame has been transmitted
+    ///
+    /// The transmission is aborted (ECON1.TXRST) after `polls` polls of ECON1, so that a wedged
+    /// ENC28J60 can't hang the caller. `yield_hook` is called between polls if there is one.
+    fn wait_transmit(&mut self, polls: u32, yield_hook: Option<fn()>) -> Result<()> {
+        for _ in 0..polls {
+            if self.read(Register::ECON1)? & ECON1_TXRTS == 0 {
+                if self.read(Register::ESTAT)? & ESTAT_TXABRT != 0 {
+                    return Err(self.transmit_error());
+                }
+                return Ok(());
+            }
+            if let Some(yield_hook) = yield_hook {
+                yield_hook();
+            }
+        }
+
+        self.set_bits(Register::ECON1, ECON1_TXRST)?;
+        self.clear_bits(Register::ECON1, ECON1_TXRST | ECON1_TXRTS)?;
+        Err(Error::TransmitTimeout)
+    }
+
+    /// Read the transmit status vector the ENC28J60 writes just after the frame ending at `tx_end`
+    fn read_transmit_status(&mut self, tx_end: u16) -> Result<[u8; TSV_SIZE]> {
+        self.write_pointer(Pointer::ERDPT, tx_end.wrapping_add(1))?;
+        let mut tsv = [0; TSV_SIZE];
+        self.read_buffer(&mut tsv)?;
+        Ok(tsv)
+    }
+
+    /// Work out why a transmission failed from ESTAT
+    fn transmit_error(&mut self) -> Error {
+        match self.read(Register::ESTAT) {
+            Ok(estat) if estat & ESTAT_LATECOL != 0 => Error::LateCollision,
+            Ok(estat) if estat & ESTAT_TXABRT != 0 => Error::TransmitAbort,
+            _ => Error::SpiTransfer,
+        }
+    }
+
+    /// Read the link status from PHSTAT2.LSTAT
+    fn read_link_up(&mut self) -> Result<bool> {
+        Ok(self.read_phy(PhyRegister::PHSTAT2)? & PHSTAT2_LSTAT != 0)
+    }
+
+    /// Read the link status and duplex mode of the PHY from PHSTAT2.LSTAT and PHSTAT2.DPXSTAT
+    fn read_link_status(&mut self) -> Result<(bool, bool)> {
+        let phstat2 = self.read_phy(PhyRegister::PHSTAT2)?;
+        Ok((phstat2 & PHSTAT2_LSTAT != 0, phstat2 & PHSTAT2_DPXSTAT != 0))
+    }
+}
+
+impl<Spi, Ncs, Int, Reset> Registers for Enc28j60<Spi, Ncs, Int, Reset>
+where
+    Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8>,
+    Ncs: OutputPin,
+    Int: enc28j60::IntPin,
+    Reset: enc28j60::ResetPin,
+{
+    fn read(&mut self, register: Register) -> Result<u8> {
+        select_bank(self, register.bank, &mut None)?;
+        read_selected(self, register)
+    }
+
+    fn write(&mut self, register: Register, value: u8) -> Result<()> {
+        select_bank(self, register.bank, &mut None)?;
+
+        self.write_control_register(register.address, value)
+            .map_err(|_| Error::SpiTransfer)
+    }
+
+    fn set_bits(&mut self, register: Register, mask: u8) -> Result<()> {
+        select_bank(self, register.bank, &mut None)?;
+
+        self.bit_field_set(register.address, mask)
+            .map_err(|_| Error::SpiTransfer)
+    }
+
+    fn clear_bits(&mut self, register: Register, mask: u8) -> Result<()> {
+        select_bank(self, register.bank, &mut None)?;
+
+        self.bit_field_clear(register.address, mask)
+            .map_err(|_| Error::SpiTransfer)
+    }
+
+    fn read_buffer(&mut self, buffer: &mut [u8]) -> Result<()> {
+        self.read_buffer_memory(buffer)
+            .map_err(|_| Error::SpiTransfer)
+    }
+
+    fn write_buffer(&mut self, buffer: &[u8]) -> Result<()> {
+        self.write_buffer_memory(buffer)
+            .map_err(|_| Error::SpiTransfer)
+    }
+}
+
+/// ENC28J60 driver that remembers the selected bank, skipping the bank switch when a register is
+/// in the bank already selected
+///
+/// The bank must be forgotten whenever ECON1.BSEL may have changed behind its back, such as by a
+/// reset or by the driver being used directly.
+pub(crate) struct BankedEnc28j60<Spi, Ncs, Int, Reset> {
+    device: Enc28j60<Spi, Ncs, Int, Reset>,
+    bank: Option<Bank>,
+    #[cfg(feature = "metrics")]
+    spi_stats: SpiStats,
+}
+
+impl<Spi, Ncs, Int, Reset> BankedEnc28j60<Spi, Ncs, Int, Reset> {
+    pub(crate) fn new(device: Enc28j60<Spi, Ncs, Int, Reset>) -> Self {
+        BankedEnc28j60 {
+            device,
+            bank: None,
+            #[cfg(feature = "metrics")]
+            spi_stats: SpiStats::default(),
+        }
+    }
+
+    pub(crate) fn into_inner(self) -> Enc28j60<Spi, Ncs, Int, Reset> {
+        self.device
+    }
+
+    /// Forget the selected bank, so that it is selected again on the next register access
+    pub(crate) fn forget_bank(&mut self) {
+        self.bank = None;
+    }
+
+    /// Buffer memory transfers made through this driver
+    #[cfg(feature = "metrics")]
+    pub(crate) fn spi_stats(&self) -> SpiStats {
+        self.spi_stats
+    }
+
+    #[cfg(feature = "metrics")]
+    pub(crate) fn reset_spi_stats(&mut self) {
+        self.spi_stats = SpiStats::default();
+    }
+
+    /// Count a buffer memory transfer of `len` bytes
+    #[cfg(feature = "metrics")]
+    fn count_transfer(&mut self, len: usize) {
+        self.spi_stats.bytes = self.spi_stats.bytes.saturating_add(len as u64);
+        self.spi_stats.transfers = self.spi_stats.transfers.saturating_add(1);
+    }
+}
+
+impl<Spi, Ncs, Int, Reset> Deref for BankedEnc28j60<Spi, Ncs, Int, Reset> {
+    type Target = Enc28j60<Spi, Ncs, Int, Reset>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.device
+    }
+}
+
+impl<Spi, Ncs, Int, Reset> DerefMut for BankedEnc28j60<Spi, Ncs, Int, Reset> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.device
+    }
+}
+
+impl<Spi, Ncs, Int, Reset> BankedEnc28j60<Spi, Ncs, Int, Reset>
+where
+    Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8>,
+    Ncs: OutputPin,
+    Int: enc28j60::IntPin,
+    Reset: enc28j60::ResetPin,
+{
+    /// Select `bank` once for a batch of accesses to registers in it and the common registers
+    ///
+    /// The selected bank is remembered anyway, this groups the accesses so they issue a single
+    /// bank switch. Debug builds check that `f` left `bank` selected, catching an access to another
+    /// bank slipping into the batch.
+    pub(crate) fn with_bank<R, F>(&mut self, bank: Bank, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut Self) -> Result<R>,
+    {
+        select_bank(&mut self.device, bank, &mut self.bank)?;
+        let result = f(self);
+        debug_assert!(
+            result.is_err() || bank.bsel().is_none() || self.bank == Some(bank),
+            "register access outside the batched bank"
+        );
+        result
+    }
+}
+
+impl<Spi, Ncs, Int, Reset> Registers for BankedEnc28j60<Spi, Ncs, Int, Reset>
+where
+    Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8>,
+    Ncs: OutputPin,
+    Int: enc28j60::IntPin,
+    Reset: enc28j60::ResetPin,
+{
+    fn read(&mut self, register: Register) -> Result<u8> {
+        select_bank(&mut self.device, register.bank, &mut self.bank)?;
+        read_selected(&mut self.device, register)
+    }
+
+    fn write(&mut self, register: Register, value: u8) -> Result<()> {
+        select_bank(&mut self.device, register.bank, &mut self.bank)?;
+
+        self.device
+            .write_control_register(register.address, value)
+            .map_err(|_| Error::SpiTransfer)
+    }
+
+    fn set_bits(&mut self, register: Register, mask: u8) -> Result<()> {
+        select_bank(&mut self.device, register.bank, &mut self.bank)?;
+
+        self.device
+            .bit_field_set(register.address, mask)
+            .map_err(|_| Error::SpiTransfer)
+    }
+
+    fn clear_bits(&mut self, register: Register, mask: u8) -> Result<()> {
+        select_bank(&mut self.device, register.bank, &mut self.bank)?;
+
+        self.device
+            .bit_field_clear(register.address, mask)
+            .map_err(|_| Error::SpiTransfer)
+    }
+
+    fn read_buffer(&mut self, buffer: &mut [u8]) -> Result<()> {
+        self.device.read_buffer(buffer)?;
+        #[cfg(feature = "metrics")]
+        self.count_transfer(buffer.len());
+        Ok(())
+    }
+
+    fn write_buffer(&mut self, buffer: &[u8]) -> Result<()> {
+        self.device.write_buffer(buffer)?;
+        #[cfg(feature = "metrics")]
+        self.count_transfer(buffer.len());
+        Ok(())
+    }
+}
+
+/// Read a register in the bank already selected
+fn read_selected<Spi, Ncs, Int, Reset>(
+    device: &mut Enc28j60<Spi, Ncs, Int, Reset>,
+    register: Register,
+) -> Result<u8>
+where
+    Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8>,
+    Ncs: OutputPin,
+    Int: enc28j60::IntPin,
+    Reset: enc28j60::ResetPin,
+{
+    if register.mac_mii {
+        device.read_mac_register(register.address)
+    } else {
+        device.read_control_register(register.address)
+    }
+    .map_err(|_| Error::SpiTransfer)
+}
+
+/// Select `bank` through ECON1.BSEL unless it is the `selected` one, which is then updated
+fn select_bank<Spi, Ncs, Int, Reset>(
+    device: &mut Enc28j60<Spi, Ncs, Int, Reset>,
+    bank: Bank,
+    selected: &mut Option<Bank>,
+) -> Result<()>
+where
+    Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8>,
+    Ncs: OutputPin,
+    Int: enc28j60::IntPin,
+    Reset: enc28j60::ResetPin,
+{
+    let bsel = match bank.bsel() {
+        Some(bsel) if *selected != Some(bank) => bsel,
+        _ => return Ok(()),
+    };
+
+    // Forgotten until the switch completes, in case it fails half way
+    *selected = None;
+    device
+        .bit_field_clear(Register::ECON1.address, ECON1_BSEL)
+        .map_err(|_| Error::SpiTransfer)?;
+
+    if bsel != 0 {
+        device
+            .bit_field_set(Register::ECON1.address, bsel)
+            .map_err(|_| Error::SpiTransfer)?;
+    }
+
+    *selected = Some(bank);
+    Ok(())
+}
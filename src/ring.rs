@@ -0,0 +1,54 @@
+//! Software RX ring, buffering received frames ahead of smoltcp
+
+use heapless::{Deque, Vec};
+
+use crate::BUFFER_SIZE;
+
+/// Received frame held in the ring set through `SmolEnc28j60::set_rx_ring`
+pub type Frame = Vec<u8, BUFFER_SIZE>;
+
+/// Ring of received frames of any depth
+pub(crate) trait FrameRing {
+    fn is_full(&self) -> bool;
+
+    fn is_empty(&self) -> bool;
+
+    /// Append an empty frame to be received into
+    fn push_empty(&mut self) -> Option<&mut Frame>;
+
+    /// Drop the frame last appended, when it couldn't be received
+    fn discard_back(&mut self);
+
+    /// Oldest frame in the ring
+    fn front_mut(&mut self) -> Option<&mut Frame>;
+
+    /// Drop the oldest frame, once it has been consumed
+    fn discard_front(&mut self);
+}
+
+impl<const R: usize> FrameRing for Deque<Frame, R> {
+    fn is_full(&self) -> bool {
+        Deque::is_full(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        Deque::is_empty(self)
+    }
+
+    fn push_empty(&mut self) -> Option<&mut Frame> {
+        self.push_back(Frame::new()).ok()?;
+        self.back_mut()
+    }
+
+    fn discard_back(&mut self) {
+        self.pop_back();
+    }
+
+    fn front_mut(&mut self) -> Option<&mut Frame> {
+        Deque::front_mut(self)
+    }
+
+    fn discard_front(&mut self) {
+        self.pop_front();
+    }
+}
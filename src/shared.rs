@@ -0,0 +1,78 @@
+//! Wrapper whose shared references implement the smoltcp Device trait
+
+use embedded_hal::blocking;
+use embedded_hal::digital::v2::OutputPin;
+use smoltcp::phy::{Device as SmolDevice, DeviceCapabilities};
+
+use crate::{RxToken, SmolEnc28j60, TxToken, BUFFER_SIZE};
+
+/// [SmolEnc28j60] that smoltcp can use through a shared reference
+///
+/// `&SharedSmolEnc28j60` implements the smoltcp Device trait, so the poll loop and another part of
+/// the firmware, such as an interrupt handler draining frames, can both hold a reference. Access
+/// is serialized by the locking the tokens already do: a token that finds the device or its frame
+/// buffer in use fails with [smoltcp::Error::Exhausted] or [smoltcp::Error::Illegal] rather than
+/// panicking, and the operation can be retried. The wrapper is not `Sync`, so sharing it with an
+/// interrupt handler still needs something like a critical section mutex around it.
+pub struct SharedSmolEnc28j60<Spi, Ncs, Int, Reset, const N: usize = BUFFER_SIZE>
+where
+    Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8>,
+    Ncs: OutputPin,
+    Int: enc28j60::IntPin,
+    Reset: enc28j60::ResetPin,
+{
+    device: SmolEnc28j60<Spi, Ncs, Int, Reset, N>,
+}
+
+impl<Spi, Ncs, Int, Reset, const N: usize> SharedSmolEnc28j60<Spi, Ncs, Int, Reset, N>
+where
+    Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8>,
+    Ncs: OutputPin,
+    Int: enc28j60::IntPin,
+    Reset: enc28j60::ResetPin,
+{
+    /// Wrap a device so it can be shared
+    pub fn new(device: SmolEnc28j60<Spi, Ncs, Int, Reset, N>) -> Self {
+        SharedSmolEnc28j60 { device }
+    }
+
+    /// The wrapped device, for the status methods of [SmolEnc28j60]
+    pub fn inner(&self) -> &SmolEnc28j60<Spi, Ncs, Int, Reset, N> {
+        &self.device
+    }
+
+    /// The wrapped device, for the configuration methods of [SmolEnc28j60]
+    pub fn inner_mut(&mut self) -> &mut SmolEnc28j60<Spi, Ncs, Int, Reset, N> {
+        &mut self.device
+    }
+
+    /// Unwrap the device
+    pub fn into_inner(self) -> SmolEnc28j60<Spi, Ncs, Int, Reset, N> {
+        self.device
+    }
+}
+
+impl<'a, 'b: 'a, Spi, Ncs, Int, Reset, const N: usize> SmolDevice<'a>
+    for &'b SharedSmolEnc28j60<Spi, Ncs, Int, Reset, N>
+where
+    Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8> + 'a,
+    Ncs: OutputPin + 'a,
+    Int: enc28j60::IntPin + 'a,
+    Reset: enc28j60::ResetPin + 'a,
+{
+    type RxToken = RxToken<'a, Spi, Ncs, Int, Reset, N>;
+
+    type TxToken = TxToken<'a, Spi, Ncs, Int, Reset, N>;
+
+    fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
+        self.device.rx_tokens()
+    }
+
+    fn transmit(&'a mut self) -> Option<Self::TxToken> {
+        self.device.tx_token()
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        self.device.capabilities()
+    }
+}
@@ -0,0 +1,74 @@
+//! RX and TX halves of a wrapper, see [SmolEnc28j60::split]
+
+use embedded_hal::blocking;
+use embedded_hal::digital::v2::OutputPin;
+
+use crate::{RxToken, SmolEnc28j60, TxToken, BUFFER_SIZE};
+
+/// Receiving half of a [SmolEnc28j60], see [SmolEnc28j60::split]
+pub struct RxHalf<'a, Spi, Ncs, Int, Reset, const N: usize = BUFFER_SIZE>
+where
+    Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8>,
+    Ncs: OutputPin,
+    Int: enc28j60::IntPin,
+    Reset: enc28j60::ResetPin,
+{
+    device: &'a SmolEnc28j60<Spi, Ncs, Int, Reset, N>,
+}
+
+impl<'a, Spi, Ncs, Int, Reset, const N: usize> RxHalf<'a, Spi, Ncs, Int, Reset, N>
+where
+    Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8>,
+    Ncs: OutputPin,
+    Int: enc28j60::IntPin,
+    Reset: enc28j60::ResetPin,
+{
+    pub(crate) fn new(device: &'a SmolEnc28j60<Spi, Ncs, Int, Reset, N>) -> Self {
+        RxHalf { device }
+    }
+
+    /// Token for the next received frame, or `None` if no frame is waiting or the device is in
+    /// use by the TX half
+    pub fn receive(&self) -> Option<RxToken<'a, Spi, Ncs, Int, Reset, N>> {
+        self.device.rx_token()
+    }
+
+    /// See [SmolEnc28j60::pending_packets]
+    pub fn pending_packets(&self) -> u8 {
+        self.device.pending_packets()
+    }
+}
+
+/// Transmitting half of a [SmolEnc28j60], see [SmolEnc28j60::split]
+pub struct TxHalf<'a, Spi, Ncs, Int, Reset, const N: usize = BUFFER_SIZE>
+where
+    Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8>,
+    Ncs: OutputPin,
+    Int: enc28j60::IntPin,
+    Reset: enc28j60::ResetPin,
+{
+    device: &'a SmolEnc28j60<Spi, Ncs, Int, Reset, N>,
+}
+
+impl<'a, Spi, Ncs, Int, Reset, const N: usize> TxHalf<'a, Spi, Ncs, Int, Reset, N>
+where
+    Spi: blocking::spi::Transfer<u8> + blocking::spi::Write<u8>,
+    Ncs: OutputPin,
+    Int: enc28j60::IntPin,
+    Reset: enc28j60::ResetPin,
+{
+    pub(crate) fn new(device: &'a SmolEnc28j60<Spi, Ncs, Int, Reset, N>) -> Self {
+        TxHalf { device }
+    }
+
+    /// Token to transmit a frame, or `None` if a frame is still being transmitted or the device
+    /// is in use by the RX half
+    pub fn transmit(&self) -> Option<TxToken<'a, Spi, Ncs, Int, Reset, N>> {
+        self.device.tx_token()
+    }
+
+    /// See [SmolEnc28j60::tx_ready]
+    pub fn tx_ready(&self) -> bool {
+        self.device.tx_ready()
+    }
+}
@@ -0,0 +1,954 @@
+//! Tests of the wrapper against the simulated ENC28J60 in [crate::mock]
+
+use std::cell::RefCell;
+use std::format;
+use std::vec::Vec;
+
+use embedded_hal_mock::delay::MockNoop;
+use enc28j60::{Enc28j60, Unconnected};
+use smoltcp::phy::{Device, RxToken, TxToken};
+use smoltcp::time::Instant;
+
+use crate::mock::{
+    Ncs, Op, Reg, Sim, Spi, ECON1_TXRST, ECON1_TXRTS, EIR_RXERIF, RSV_BROADCAST, RSV_CRC_ERROR,
+    RSV_LENGTH_CHECK_ERROR, RSV_MULTICAST, RSV_RECEIVED_OK,
+};
+use crate::register::{
+    Pointer, Register, Registers, CLKRDY_POLLS, ECON1_RXEN, ECON2_PKTDEC, EIE_INTIE, EIE_LINKIE,
+    EIR_PKTIF, ERXFCON_BCEN, ERXFCON_CRCEN, ERXFCON_HTEN, ERXFCON_MCEN, ERXFCON_UCEN,
+};
+use crate::{
+    hash_table, Error, Layout, Revision, RevisionError, SharedSmolEnc28j60, SmolEnc28j60, TxStatus,
+    BUFFER_SIZE, INIT_RX_BUFFER_SIZE,
+};
+
+type Driver = Enc28j60<Spi, Ncs, Unconnected, Unconnected>;
+
+type Wrapper = SmolEnc28j60<Spi, Ncs, Unconnected, Unconnected>;
+
+const MAC: [u8; 6] = [0x02, 0x00, 0x00, 0x12, 0x34, 0x56];
+
+/// Driver initialised against a fresh simulated chip
+fn driver(sim: &Sim) -> Driver {
+    Enc28j60::new(
+        sim.spi(),
+        sim.ncs(),
+        Unconnected,
+        Unconnected,
+        &mut MockNoop::new(),
+        INIT_RX_BUFFER_SIZE,
+        MAC,
+    )
+    .unwrap()
+}
+
+/// Wrapper around a driver initialised against a fresh simulated chip, with the log of the
+/// initialisation cleared
+fn wrapper() -> (Wrapper, Sim) {
+    let sim = Sim::default();
+    let device = SmolEnc28j60::from(driver(&sim));
+    sim.chip().log.clear();
+    (device, sim)
+}
+
+/// Frame of `len` bytes addressed to [MAC], with a payload counting up from 0
+fn frame(len: usize) -> Vec<u8> {
+    let mut frame: Vec<u8> = (0..len).map(|i| i as u8).collect();
+    frame[..6].copy_from_slice(&MAC);
+    frame
+}
+
+#[test]
+fn rx_token_sees_the_received_frame_length() {
+    let (mut device, sim) = wrapper();
+    sim.inject(&frame(64));
+
+    let (rx, _tx) = device.receive().unwrap();
+    let len = rx.consume(Instant::from_millis(0), |frame| Ok(frame.len()));
+
+    assert_eq!(len, Ok(64));
+}
+
+#[test]
+fn promiscuous_mode_disables_every_filter_but_the_crc_check() {
+    let (mut device, sim) = wrapper();
+
+    device.set_promiscuous(true).unwrap();
+    let writes: Vec<Op> = sim
+        .chip()
+        .log
+        .iter()
+        .filter(|op| matches!(op, Op::Write(..)))
+        .copied()
+        .collect();
+    let expected: Vec<Op> = (0..8)
+        .map(|i| Op::Write(Reg(Reg::EHT0.0, Reg::EHT0.1 + i), 0))
+        .chain([Op::Write(Reg::ERXFCON, ERXFCON_CRCEN)])
+        .collect();
+    assert_eq!(writes, expected);
+
+    device.set_promiscuous(false).unwrap();
+    assert_eq!(
+        sim.chip().writes(Reg::ERXFCON).last(),
+        Some(&(ERXFCON_UCEN | ERXFCON_CRCEN | ERXFCON_BCEN))
+    );
+}
+
+#[test]
+fn no_rx_token_while_no_frame_is_pending() {
+    let (mut device, sim) = wrapper();
+
+    assert_eq!(device.pending_packets(), 0);
+    assert!(device.receive().is_none());
+    assert!(!sim
+        .chip()
+        .log
+        .iter()
+        .any(|op| matches!(op, Op::ReadBuffer(_))));
+
+    sim.inject(&frame(64));
+    assert_eq!(device.pending_packets(), 1);
+    assert!(device.receive().is_some());
+}
+
+#[test]
+fn reset_restores_the_filters_and_interrupt_enables() {
+    let (mut device, sim) = wrapper();
+    device.set_multicast(true).unwrap();
+    device.enable_link_change_interrupt().unwrap();
+
+    device.reset_with_command(&mut MockNoop::new()).unwrap();
+
+    let chip = sim.chip();
+    assert_eq!(
+        chip.register(Reg::ERXFCON),
+        ERXFCON_UCEN | ERXFCON_CRCEN | ERXFCON_BCEN | ERXFCON_MCEN
+    );
+    assert_eq!(chip.register(Reg::EIE), EIE_INTIE | EIE_LINKIE);
+}
+
+#[test]
+fn receive_counts_and_clears_a_buffer_overflow() {
+    let (mut device, sim) = wrapper();
+    sim.inject(&frame(64));
+    sim.overflow();
+
+    let (rx, _tx) = device.receive().unwrap();
+    assert_eq!(rx.consume(Instant::from_millis(0), |_| Ok(())), Ok(()));
+
+    assert_eq!(device.stats().rx_overflows, 1);
+    assert_eq!(device.last_error(), Some(Error::BufferOverflow));
+    assert_eq!(sim.chip().register(Reg::EIR) & EIR_RXERIF, 0);
+}
+
+#[test]
+fn multicast_filters_are_written_to_erxfcon_and_the_hash_table() {
+    let (mut device, sim) = wrapper();
+
+    device.set_multicast(true).unwrap();
+    assert_eq!(
+        sim.chip().writes(Reg::ERXFCON).last(),
+        Some(&(ERXFCON_UCEN | ERXFCON_CRCEN | ERXFCON_BCEN | ERXFCON_MCEN))
+    );
+
+    let mdns = [0x01, 0x00, 0x5E, 0x00, 0x00, 0xFB];
+    device.set_multicast(false).unwrap();
+    device.multicast_hash(&[mdns]).unwrap();
+    let table = hash_table(&[mdns]);
+    assert_eq!(table.iter().map(|byte| byte.count_ones()).sum::<u32>(), 1);
+    let chip = sim.chip();
+    for (i, entry) in table.iter().enumerate() {
+        let reg = Reg(Reg::EHT0.0, Reg::EHT0.1 + i as u8);
+        assert_eq!(chip.writes(reg).last(), Some(entry));
+    }
+    assert_eq!(
+        chip.writes(Reg::ERXFCON).last(),
+        Some(&(ERXFCON_UCEN | ERXFCON_CRCEN | ERXFCON_BCEN | ERXFCON_HTEN))
+    );
+
+    drop(chip);
+    device.multicast_hash(&[]).unwrap();
+    assert_eq!(sim.chip().writes(Reg::EHT7).last(), Some(&0));
+}
+
+#[test]
+fn into_inner_leaves_the_device_running() {
+    let (mut device, sim) = wrapper();
+    device.enable_link_change_interrupt().unwrap();
+
+    let driver = device.into_inner();
+
+    assert_eq!(sim.chip().register(Reg::ECON1) & ECON1_RXEN, ECON1_RXEN);
+    assert_eq!(sim.chip().register(Reg::EIE), EIE_INTIE | EIE_LINKIE);
+    let mut device = SmolEnc28j60::from(driver);
+    sim.inject(&frame(64));
+    assert!(device.receive().is_some());
+}
+
+#[test]
+fn max_burst_size_follows_the_rx_buffer() {
+    let (mut device, _sim) = wrapper();
+    assert_eq!(device.capabilities().max_burst_size, Some(4));
+
+    device.set_buffer_split(0x0000, 0x0FFF).unwrap();
+    assert_eq!(device.capabilities().max_burst_size, Some(2));
+
+    device.set_max_burst_size(None);
+    assert_eq!(device.capabilities().max_burst_size, None);
+}
+
+#[test]
+fn oversize_tx_frame_fails_without_calling_the_closure() {
+    let (mut device, sim) = wrapper();
+
+    let tx = device.transmit().unwrap();
+    let mut called = false;
+    let result = tx.consume(Instant::from_millis(0), BUFFER_SIZE + 1, |_| {
+        called = true;
+        Ok(())
+    });
+
+    assert!(!called);
+    assert_eq!(result, Err(smoltcp::Error::Illegal));
+    assert_eq!(device.last_error(), Some(Error::FrameTooLong));
+    assert!(sim.chip().transmitted.is_empty());
+}
+
+#[test]
+fn zero_on_receive_clears_the_tail_left_by_a_longer_frame() {
+    let (mut device, sim) = wrapper();
+    device.set_zero_on_receive(true);
+    sim.inject(&frame(200));
+    sim.inject(&frame(64));
+
+    for len in [200, 64] {
+        let (rx, _tx) = device.receive().unwrap();
+        assert_eq!(
+            rx.consume(Instant::from_millis(0), |frame| Ok(frame.len())),
+            Ok(len)
+        );
+    }
+
+    let buffer = device.device.rx_buffer.borrow();
+    assert_eq!(buffer[..64], frame(64)[..]);
+    assert!(buffer[64..].iter().all(|&byte| byte == 0));
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn waiting_for_a_frame_masks_the_other_interrupt_sources() {
+    use crate::register::{EIE_PKTIE, EIR_DMAIF, EIR_TXERIF, EIR_TXIF};
+
+    let (mut device, sim) = wrapper();
+    device.enable_link_change_interrupt().unwrap();
+
+    device.device.mask_to_packet_interrupt().unwrap();
+    let chip = sim.chip();
+    assert_eq!(chip.register(Reg::EIE), EIE_INTIE | EIE_PKTIE);
+    let flags = EIR_DMAIF | EIR_TXIF | EIR_TXERIF;
+    assert!(chip.log.contains(&Op::Clear(Reg::EIR, flags)));
+
+    drop(chip);
+    device.device.unmask_interrupts().unwrap();
+    assert_eq!(
+        sim.chip().register(Reg::EIE),
+        EIE_INTIE | EIE_PKTIE | EIE_LINKIE
+    );
+}
+
+#[test]
+fn reading_the_last_frame_decrements_the_packet_count_to_zero() {
+    let (mut device, sim) = wrapper();
+    sim.inject(&frame(64));
+
+    let (rx, _tx) = device.receive().unwrap();
+    rx.consume(Instant::from_millis(0), |_| Ok(())).unwrap();
+
+    assert!(sim.chip().log.contains(&Op::Set(Reg::ECON2, ECON2_PKTDEC)));
+    assert_eq!(device.pending_packets(), 0);
+    assert_eq!(sim.chip().register(Reg::EIR) & EIR_PKTIF, 0);
+}
+
+#[test]
+fn transmit_resets_a_stalled_transmit_logic_first() {
+    let (mut device, sim) = wrapper();
+    sim.stall_transmit();
+
+    let tx = device.transmit().unwrap();
+    let result = tx.consume(Instant::from_millis(0), 64, |buffer| {
+        buffer.copy_from_slice(&frame(64));
+        Ok(())
+    });
+    assert_eq!(result, Ok(()));
+
+    let chip = sim.chip();
+    let position = |op| chip.log.iter().position(|logged| *logged == op);
+    let reset = position(Op::Set(Reg::ECON1, ECON1_TXRST)).unwrap();
+    let start = position(Op::Set(Reg::ECON1, ECON1_TXRTS)).unwrap();
+    assert!(reset < start);
+    assert_eq!(chip.transmitted, [frame(64)]);
+}
+
+#[test]
+fn wedged_transmit_times_out_and_is_aborted() {
+    let (mut device, sim) = wrapper();
+    sim.chip().transmit_stuck = true;
+    device.set_transmit_timeout(10);
+
+    assert_eq!(device.write_frame(&frame(64)), Err(Error::TransmitTimeout));
+
+    let chip = sim.chip();
+    assert!(chip.transmitted.is_empty());
+    let abort = [
+        Op::Set(Reg::ECON1, ECON1_TXRST),
+        Op::Clear(Reg::ECON1, ECON1_TXRST | ECON1_TXRTS),
+    ];
+    assert!(chip.log.ends_with(&abort));
+}
+
+#[test]
+fn transmit_status_vector_is_parsed_and_errors_counted() {
+    let (mut device, sim) = wrapper();
+    // 3 collisions and a CRC error, then a late collision after deferring
+    sim.chip().tsv = Some([64, 0, 0x13, 0x24, 0, 0, 0]);
+
+    device.write_frame(&frame(64)).unwrap();
+
+    assert_eq!(
+        device.last_tx_status(),
+        Some(TxStatus {
+            byte_count: 64,
+            collision_count: 3,
+            crc_error: true,
+            late_collision: true,
+            defer: true,
+            giant: false,
+        })
+    );
+    assert_eq!(device.stats().tx_errors, 1);
+}
+
+#[test]
+fn frame_not_received_ok_is_dropped() {
+    let (mut device, sim) = wrapper();
+    sim.inject_with(&frame(64), |header| header[4] &= !RSV_RECEIVED_OK);
+
+    let (rx, _tx) = device.receive().unwrap();
+    let mut called = false;
+    let result = rx.consume(Instant::from_millis(0), |_| {
+        called = true;
+        Ok(())
+    });
+
+    assert!(!called);
+    assert_eq!(result, Err(smoltcp::Error::Illegal));
+    assert_eq!(device.last_error(), Some(Error::InvalidFrame));
+    assert_eq!(device.stats().rx_dropped, 1);
+    assert!(matches!(device.last_rx_status(), Some(status) if !status.received_ok));
+    assert_eq!(device.pending_packets(), 0);
+}
+
+#[test]
+fn inter_packet_gaps_are_written_and_kept_across_a_duplex_change() {
+    let (mut device, sim) = wrapper();
+
+    device.set_ipg(0x20, 0x13, 0x0D).unwrap();
+    let gaps = [
+        Op::Write(Reg::MABBIPG, 0x20),
+        Op::Write(Reg::MAIPGL, 0x13),
+        Op::Write(Reg::MAIPGH, 0x0D),
+    ];
+    assert!(sim.chip().log.ends_with(&gaps));
+
+    device.set_duplex(true).unwrap();
+    let chip = sim.chip();
+    assert_eq!(chip.writes(Reg::MABBIPG).last(), Some(&0x20));
+    assert_eq!(chip.writes(Reg::MAIPGL).last(), Some(&0x13));
+    assert_eq!(chip.writes(Reg::MAIPGH).last(), Some(&0x0D));
+}
+
+#[test]
+fn system_reset_command_is_only_sent_by_reset_with_command() {
+    let (mut device, sim) = wrapper();
+
+    device.reset(&mut MockNoop::new()).unwrap();
+    assert!(!sim.chip().log.contains(&Op::Reset));
+
+    device.reset_with_command(&mut MockNoop::new()).unwrap();
+    assert_eq!(
+        sim.chip().log.iter().filter(|op| **op == Op::Reset).count(),
+        1
+    );
+}
+
+#[test]
+fn flush_rx_recovers_reception_after_an_overflow() {
+    let (mut device, sim) = wrapper();
+    sim.inject(&frame(64));
+    sim.inject(&frame(100));
+    sim.overflow();
+
+    device.flush_rx().unwrap();
+    assert_eq!(device.pending_packets(), 0);
+    assert_eq!(device.stats().rx_dropped, 2);
+    assert_eq!(sim.chip().register(Reg::EIR) & EIR_RXERIF, 0);
+    assert_eq!(sim.chip().register(Reg::ECON1) & ECON1_RXEN, ECON1_RXEN);
+
+    sim.inject(&frame(80));
+    let (rx, _tx) = device.receive().unwrap();
+    let received = rx.consume(Instant::from_millis(0), |received| Ok(received.to_vec()));
+    assert_eq!(received, Ok(frame(80)));
+}
+
+#[test]
+fn crc_and_length_errors_are_counted_and_the_frames_dropped() {
+    let (mut device, sim) = wrapper();
+    for error in [RSV_CRC_ERROR, RSV_LENGTH_CHECK_ERROR] {
+        sim.inject_with(&frame(64), |header| header[4] = error);
+    }
+
+    for _ in 0..2 {
+        let (rx, _tx) = device.receive().unwrap();
+        let result = rx.consume(Instant::from_millis(0), |_| Ok(()));
+        assert_eq!(result, Err(smoltcp::Error::Illegal));
+    }
+
+    let stats = device.stats();
+    assert_eq!(stats.rx_crc_errors, 1);
+    assert_eq!(stats.rx_align_errors, 1);
+    assert_eq!(stats.rx_dropped, 2);
+    assert_eq!(stats.rx_packets, 0);
+}
+
+#[test]
+fn spi_failure_is_recorded_as_a_bus_error() {
+    let (mut device, sim) = wrapper();
+
+    sim.chip().failures = 1;
+    assert_eq!(device.write_frame(&frame(64)), Err(Error::SpiTransfer));
+    assert!(sim.chip().transmitted.is_empty());
+
+    sim.inject(&frame(64));
+    let (rx, _tx) = device.receive().unwrap();
+    sim.chip().failures = 1;
+    assert_eq!(
+        rx.consume(Instant::from_millis(0), |_| Ok(())),
+        Err(smoltcp::Error::Illegal)
+    );
+    assert_eq!(device.last_error(), Some(Error::SpiTransfer));
+    assert!(Error::SpiTransfer.is_bus_error());
+    assert!(!Error::TransmitAbort.is_bus_error());
+}
+
+#[test]
+fn second_token_for_the_same_frame_finds_nothing_to_receive() {
+    let (device, sim) = wrapper();
+    let shared = SharedSmolEnc28j60::new(device);
+    sim.inject(&frame(64));
+
+    let (mut poll, mut handler) = (&shared, &shared);
+    let (first, _tx) = poll.receive().unwrap();
+    let (second, _tx) = handler.receive().unwrap();
+
+    let received = first.consume(Instant::from_millis(0), |frame| Ok(frame.len()));
+    assert_eq!(received, Ok(64));
+    let mut called = false;
+    let result = second.consume(Instant::from_millis(0), |_| {
+        called = true;
+        Ok(())
+    });
+    assert!(!called);
+    assert_eq!(result, Err(smoltcp::Error::Exhausted));
+    assert_eq!(shared.inner().last_error(), None);
+    assert_eq!(shared.inner().stats().rx_dropped, 0);
+}
+
+#[test]
+fn next_packet_pointer_out_of_the_rx_buffer_restarts_reception() {
+    let (mut device, sim) = wrapper();
+    sim.inject_with(&frame(64), |header| {
+        header[..2].copy_from_slice(&[0x00, 0x1F])
+    });
+    sim.inject(&frame(64));
+
+    let (rx, _tx) = device.receive().unwrap();
+    let result = rx.consume(Instant::from_millis(0), |_| Ok(()));
+    assert_eq!(result, Err(smoltcp::Error::Illegal));
+    assert_eq!(device.last_error(), Some(Error::InvalidFrame));
+    assert_eq!(device.pending_packets(), 0);
+    assert_eq!(device.stats().rx_dropped, 2);
+
+    sim.inject(&frame(80));
+    let (rx, _tx) = device.receive().unwrap();
+    let received = rx.consume(Instant::from_millis(0), |received| Ok(received.to_vec()));
+    assert_eq!(received, Ok(frame(80)));
+}
+
+/// Bank switches in the log, each starting by clearing ECON1.BSEL
+fn bank_selects(sim: &Sim) -> usize {
+    let select = Op::Clear(Reg::ECON1, 0b11);
+    sim.chip().log.iter().filter(|op| **op == select).count()
+}
+
+#[test]
+fn consecutive_reads_in_one_bank_select_it_once() {
+    let (device, sim) = wrapper();
+    let mut lower = device.device.device().unwrap();
+    lower.forget_bank();
+
+    lower.read(Register::EPKTCNT).unwrap();
+    lower.read(Register::ERXFCON).unwrap();
+    assert_eq!(bank_selects(&sim), 1);
+
+    // The common registers are reachable from any bank
+    lower.read(Register::EIR).unwrap();
+    lower.read(Register::EPKTCNT).unwrap();
+    assert_eq!(bank_selects(&sim), 1);
+
+    lower.read_pointer(Pointer::ERXST).unwrap();
+    assert_eq!(bank_selects(&sim), 2);
+}
+
+std::thread_local! {
+    /// Simulated chip that [drop_100_under_traffic] receives a frame into each time it is called
+    static TRAFFIC: RefCell<Option<Sim>> = RefCell::new(None);
+}
+
+/// Software receive filter dropping frames of 100 bytes, while new frames keep arriving
+fn drop_100_under_traffic(received: &[u8]) -> bool {
+    TRAFFIC.with(|sim| sim.borrow().as_ref().unwrap().inject(&frame(64)));
+    received.len() != 100
+}
+
+#[test]
+fn software_filter_only_reads_the_frames_waiting_on_entry() {
+    let (mut device, sim) = wrapper();
+    TRAFFIC.with(|traffic| *traffic.borrow_mut() = Some(sim.clone()));
+    device.set_rx_filter(Some(drop_100_under_traffic));
+    sim.inject(&frame(100));
+    sim.inject(&frame(100));
+
+    let (rx, _tx) = device.receive().unwrap();
+    let result = rx.consume(Instant::from_millis(0), |_| Ok(()));
+
+    assert_eq!(result, Err(smoltcp::Error::Exhausted));
+    assert_eq!(device.stats().rx_filtered, 2);
+    assert_eq!(device.pending_packets(), 2);
+}
+
+#[test]
+fn drain_counts_the_frames_the_software_filter_dropped() {
+    let (mut device, sim) = wrapper();
+    TRAFFIC.with(|traffic| *traffic.borrow_mut() = Some(sim.clone()));
+    device.set_rx_filter(Some(drop_100_under_traffic));
+    for len in [64, 100, 64] {
+        sim.inject(&frame(len));
+    }
+
+    let mut lengths = Vec::new();
+    assert_eq!(device.drain(|frame| lengths.push(frame.len())), Ok(2));
+
+    assert_eq!(lengths, [64, 64]);
+    assert_eq!(device.stats().rx_filtered, 1);
+    assert_eq!(device.pending_packets(), 3);
+}
+
+#[test]
+fn zero_length_transmit_touches_no_hardware() {
+    let (mut device, sim) = wrapper();
+
+    let tx = device.transmit().unwrap();
+    let result = tx.consume(Instant::from_millis(0), 0, |buffer| Ok(buffer.len()));
+
+    assert_eq!(result, Ok(0));
+    let chip = sim.chip();
+    assert!(chip.transmitted.is_empty());
+    assert!(!chip
+        .log
+        .iter()
+        .any(|op| matches!(op, Op::WriteBuffer(_) | Op::Set(Reg::ECON1, ECON1_TXRTS))));
+}
+
+#[test]
+fn try_new_gives_up_on_an_oscillator_that_never_starts() {
+    let sim = Sim::default();
+    let enc = driver(&sim);
+    sim.chip().clock_ready = false;
+    sim.chip().log.clear();
+
+    assert_eq!(
+        Wrapper::try_new(enc).map(drop),
+        Err(RevisionError::NotReady)
+    );
+    let chip = sim.chip();
+    let polls = chip
+        .log
+        .iter()
+        .filter(|op| **op == Op::Read(Reg::ESTAT))
+        .count();
+    assert_eq!(polls, CLKRDY_POLLS);
+}
+
+#[test]
+fn rejecting_broadcast_clears_bcen_unless_promiscuous() {
+    let (mut device, sim) = wrapper();
+
+    device.set_accept_broadcast(false).unwrap();
+    assert_eq!(
+        sim.chip().writes(Reg::ERXFCON).last(),
+        Some(&(ERXFCON_UCEN | ERXFCON_CRCEN))
+    );
+
+    device.set_multicast(true).unwrap();
+    assert_eq!(
+        sim.chip().writes(Reg::ERXFCON).last(),
+        Some(&(ERXFCON_UCEN | ERXFCON_CRCEN | ERXFCON_MCEN))
+    );
+
+    device.set_promiscuous(true).unwrap();
+    assert_eq!(sim.chip().writes(Reg::ERXFCON).last(), Some(&ERXFCON_CRCEN));
+
+    device.set_promiscuous(false).unwrap();
+    device.set_accept_broadcast(true).unwrap();
+    assert_eq!(
+        sim.chip().writes(Reg::ERXFCON).last(),
+        Some(&(ERXFCON_UCEN | ERXFCON_CRCEN | ERXFCON_BCEN | ERXFCON_MCEN))
+    );
+}
+
+#[test]
+fn frame_claiming_an_oversize_length_is_dropped_unread() {
+    let (mut device, sim) = wrapper();
+    sim.inject_with(&frame(64), |header| {
+        header[2..4].copy_from_slice(&0x0700u16.to_le_bytes())
+    });
+    sim.inject(&frame(80));
+
+    let (rx, _tx) = device.receive().unwrap();
+    let result = rx.consume(Instant::from_millis(0), |_| Ok(()));
+    assert_eq!(result, Err(smoltcp::Error::Illegal));
+    assert_eq!(device.last_error(), Some(Error::InvalidFrame));
+    assert_eq!(device.stats().rx_dropped, 1);
+    assert!(!sim
+        .chip()
+        .log
+        .iter()
+        .any(|op| matches!(op, Op::ReadBuffer(len) if *len > 6)));
+
+    let (rx, _tx) = device.receive().unwrap();
+    let received = rx.consume(Instant::from_millis(0), |received| Ok(received.to_vec()));
+    assert_eq!(received, Ok(frame(80)));
+}
+
+#[test]
+fn transient_spi_failure_is_retried_when_enabled() {
+    let (mut device, sim) = wrapper();
+    device.set_spi_retries(1);
+
+    sim.chip().failures = 1;
+    device.write_frame(&frame(64)).unwrap();
+    assert_eq!(sim.chip().transmitted, [frame(64)]);
+
+    sim.inject(&frame(80));
+    let (rx, _tx) = device.receive().unwrap();
+    sim.chip().failures = 1;
+    let received = rx.consume(Instant::from_millis(0), |received| Ok(received.to_vec()));
+    assert_eq!(received, Ok(frame(80)));
+
+    let stats = device.stats();
+    assert_eq!(stats.spi_retries, 2);
+    assert_eq!(stats.rx_dropped, 0);
+    assert_eq!(device.last_error(), None);
+}
+
+#[test]
+fn duplex_change_writes_the_mac_under_one_bank_select() {
+    let (mut device, sim) = wrapper();
+    device.device.device().unwrap().forget_bank();
+
+    device.set_duplex(true).unwrap();
+    let chip = sim.chip();
+    let end = chip
+        .log
+        .iter()
+        .rposition(|op| matches!(op, Op::Write(Reg::MAIPGH, _)))
+        .unwrap();
+    let batch = &chip.log[..=end];
+    let select = batch
+        .iter()
+        .rposition(|op| *op == Op::Clear(Reg::ECON1, 0b11))
+        .unwrap();
+    // MACON3, MACON4 and the three inter-packet gaps
+    let writes = batch[select..]
+        .iter()
+        .filter(|op| matches!(op, Op::Write(Reg(2, _), _)))
+        .count();
+    assert_eq!(writes, 5);
+}
+
+#[test]
+fn each_revision_byte_maps_to_its_revision() {
+    for (erevid, revision) in [
+        (0x02, Revision::B1),
+        (0x04, Revision::B4),
+        (0x05, Revision::B5),
+        (0x06, Revision::B7),
+        (0x07, Revision::Unknown(0x07)),
+    ] {
+        assert_eq!(Revision::from_erevid(erevid), revision);
+    }
+}
+
+#[test]
+fn every_known_revision_resets_the_transmit_logic() {
+    for (erevid, revision) in [
+        (0x02, Revision::B1),
+        (0x04, Revision::B4),
+        (0x05, Revision::B5),
+        (0x06, Revision::B7),
+    ] {
+        let sim = Sim::default();
+        sim.chip().revision = erevid;
+        let mut device = SmolEnc28j60::from(driver(&sim));
+        assert_eq!(device.revision_enum(), revision);
+
+        sim.chip().log.clear();
+        device.write_frame(&frame(64)).unwrap();
+        assert!(sim.chip().log.contains(&Op::Set(Reg::ECON1, ECON1_TXRST)));
+    }
+}
+
+#[test]
+fn broadcast_and_multicast_follow_the_last_receive_status() {
+    let (mut device, sim) = wrapper();
+    assert!(!device.last_rx_was_broadcast());
+    assert!(!device.last_rx_was_multicast());
+
+    sim.inject_with(&frame(64), |header| header[5] = RSV_BROADCAST);
+    sim.inject_with(&frame(64), |header| header[5] = RSV_MULTICAST);
+    sim.inject(&frame(64));
+    for (broadcast, multicast) in [(true, false), (false, true), (false, false)] {
+        let (rx, _tx) = device.receive().unwrap();
+        rx.consume(Instant::from_millis(0), |_| Ok(())).unwrap();
+        assert_eq!(device.last_rx_was_broadcast(), broadcast);
+        assert_eq!(device.last_rx_was_multicast(), multicast);
+    }
+}
+
+#[test]
+fn saturated_packet_count_drains_up_to_the_write_pointer() {
+    let (mut device, sim) = wrapper();
+    for _ in 0..u8::MAX {
+        sim.inject(&frame(10));
+    }
+    assert_eq!(device.pending_packets(), u8::MAX);
+
+    // A frame arriving during the drain is left for later
+    let mut drained = 0;
+    let result = device.drain(|_| {
+        if drained == 0 {
+            sim.inject(&frame(64));
+        }
+        drained += 1;
+    });
+    assert_eq!(result, Ok(255));
+    assert_eq!(device.pending_packets(), 1);
+
+    let (rx, _tx) = device.receive().unwrap();
+    let received = rx.consume(Instant::from_millis(0), |received| Ok(received.to_vec()));
+    assert_eq!(received, Ok(frame(64)));
+}
+
+#[test]
+fn errors_display_concise_messages() {
+    assert_eq!(format!("{}", Error::SpiTransfer), "SPI transfer failed");
+    assert_eq!(
+        format!("{}", Error::LateCollision),
+        "transmit aborted: late collision"
+    );
+    assert_eq!(format!("{}", Error::BufferBusy), "device busy");
+}
+
+#[test]
+fn reentrant_consume_finds_the_buffer_busy() {
+    let (device, sim) = wrapper();
+    let shared = SharedSmolEnc28j60::new(device);
+
+    let (mut poll, mut logger) = (&shared, &shared);
+    let outer = poll.transmit().unwrap();
+    let inner = logger.transmit().unwrap();
+    let result = outer.consume(Instant::from_millis(0), 64, |buffer| {
+        buffer.copy_from_slice(&frame(64));
+        let nested = inner.consume(Instant::from_millis(0), 64, |_| Ok(()));
+        assert_eq!(nested, Err(smoltcp::Error::Exhausted));
+        Ok(())
+    });
+    assert_eq!(result, Ok(()));
+    assert_eq!(sim.chip().transmitted, [frame(64)]);
+
+    let device = shared.inner();
+    assert_eq!(device.stats().buffer_busy, 1);
+    #[cfg(debug_assertions)]
+    assert_eq!(device.reentrant_consumes(), 1);
+}
+
+/// RX start, RX end and TX start programmed into the simulated chip
+fn layout(device: &Wrapper) -> (u16, u16, u16) {
+    let layout = Layout::read(&mut *device.device.device().unwrap()).unwrap();
+    (layout.rx_start, layout.rx_end, layout.tx_start)
+}
+
+#[test]
+fn tx_buffer_overlapping_the_rx_buffer_is_rejected() {
+    let (mut device, _sim) = wrapper();
+    device.set_rx_buffer_size(0x1800).unwrap();
+    assert_eq!(layout(&device), (0x0000, 0x17FF, 0x1800));
+
+    // The TX buffer takes 1522 bytes
+    assert_eq!(device.set_tx_start(0x1000), Err(Error::InvalidLayout));
+    assert_eq!(device.set_tx_start(0x1C00), Err(Error::InvalidLayout));
+    assert_eq!(
+        device.set_buffer_split(0x0000, 0x19FF),
+        Err(Error::InvalidLayout)
+    );
+    assert_eq!(
+        device.set_buffer_split(0x0600, 0x1FFF),
+        Err(Error::InvalidLayout)
+    );
+    assert_eq!(layout(&device), (0x0000, 0x17FF, 0x1800));
+}
+
+#[test]
+fn tx_and_rx_buffers_can_be_swapped_around() {
+    let (mut device, sim) = wrapper();
+    device.set_rx_buffer_size(0x1800).unwrap();
+
+    device.set_buffer_split(0x0800, 0x17FF).unwrap();
+    device.set_tx_start(0x0000).unwrap();
+    device.set_buffer_split(0x0600, 0x1FFF).unwrap();
+    assert_eq!(layout(&device), (0x0600, 0x1FFF, 0x0000));
+
+    device.write_frame(&frame(64)).unwrap();
+    assert_eq!(sim.chip().transmitted, [frame(64)]);
+    sim.inject(&frame(80));
+    let (rx, _tx) = device.receive().unwrap();
+    let received = rx.consume(Instant::from_millis(0), |received| Ok(received.to_vec()));
+    assert_eq!(received, Ok(frame(80)));
+}
+
+#[test]
+fn try_new_rejects_a_bus_reading_back_garbage() {
+    let sim = Sim::default();
+    let enc = driver(&sim);
+    sim.chip().corrupt = Some((Reg::EPAUSL, 0b1000_0000));
+
+    assert_eq!(
+        Wrapper::try_new(enc).map(drop),
+        Err(RevisionError::SpiConfig)
+    );
+}
+
+#[test]
+fn pointers_are_written_and_read_low_byte_first() {
+    let (device, sim) = wrapper();
+    let mut lower = device.device.device().unwrap();
+
+    for (pointer, low, high) in [
+        (Pointer::EPAUS, Reg::EPAUSL, Reg::EPAUSH),
+        (Pointer::ERXRDPT, Reg::ERXRDPTL, Reg::ERXRDPTH),
+    ] {
+        // Select the bank ahead so only the pointer accesses are logged
+        lower.read_pointer(pointer).unwrap();
+        sim.chip().log.clear();
+
+        lower.write_pointer(pointer, 0x1235).unwrap();
+        assert_eq!(lower.read_pointer(pointer), Ok(0x1235));
+        assert_eq!(
+            sim.chip().log,
+            [
+                Op::Write(low, 0x35),
+                Op::Write(high, 0x12),
+                Op::Read(low),
+                Op::Read(high),
+            ]
+        );
+    }
+}
+
+/// Whether `frame` starts on a 4-byte boundary
+fn word_aligned(frame: &[u8]) -> bool {
+    frame.as_ptr() as usize % 4 == 0
+}
+
+#[test]
+fn frames_are_handed_to_the_tokens_word_aligned() {
+    let (mut device, sim) = wrapper();
+    sim.inject(&frame(64));
+
+    let (rx, tx) = device.receive().unwrap();
+    assert_eq!(
+        rx.consume(Instant::from_millis(0), |frame| Ok(word_aligned(frame))),
+        Ok(true)
+    );
+    assert_eq!(
+        tx.consume(Instant::from_millis(0), 64, |frame| Ok(word_aligned(frame))),
+        Ok(true)
+    );
+}
+
+#[test]
+fn external_buffer_is_trimmed_to_word_aligned_halves() {
+    let sim = Sim::default();
+    let buf = Vec::leak(std::vec![0; 2 * BUFFER_SIZE + 16]);
+    // Start the buffer one byte past a word boundary
+    let skip = (5 - buf.as_ptr() as usize % 4) % 4;
+    let mut device = SmolEnc28j60::with_buffer(driver(&sim), &mut buf[skip..]);
+    sim.inject(&frame(64));
+
+    let (rx, tx) = device.receive().unwrap();
+    let received = rx.consume(Instant::from_millis(0), |frame| {
+        Ok((word_aligned(frame), frame.to_vec()))
+    });
+    assert_eq!(received, Ok((true, frame(64))));
+    assert_eq!(
+        tx.consume(Instant::from_millis(0), 64, |frame| Ok(word_aligned(frame))),
+        Ok(true)
+    );
+}
+
+#[test]
+fn construction_moves_the_rx_buffer_to_0x0000() {
+    let (mut device, sim) = wrapper();
+    assert_eq!(layout(&device).0, 0x0000);
+
+    device.set_rx_buffer_size(0x1800).unwrap();
+    device.set_buffer_split(0x0600, 0x17FF).unwrap();
+    let mut device = SmolEnc28j60::from(device.into_inner());
+    assert_eq!(layout(&device), (0x0000, 0x11FF, 0x1200));
+
+    sim.inject(&frame(64));
+    let (rx, _tx) = device.receive().unwrap();
+    let received = rx.consume(Instant::from_millis(0), |received| Ok(received.to_vec()));
+    assert_eq!(received, Ok(frame(64)));
+}
+
+#[test]
+fn set_rx_buffer_size_programs_the_rx_buffer_at_0x0000() {
+    let (mut device, _sim) = wrapper();
+    device.set_rx_buffer_size(0x1800).unwrap();
+    device.set_buffer_split(0x0600, 0x17FF).unwrap();
+
+    device.set_rx_buffer_size(0x1000).unwrap();
+    assert_eq!(layout(&device), (0x0000, 0x0FFF, 0x1000));
+}
+
+#[test]
+fn hung_phy_is_a_timeout_rather_than_a_bus_error() {
+    let (mut device, sim) = wrapper();
+    device.set_spi_retries(3);
+
+    sim.chip().mii_busy = true;
+    assert_eq!(device.set_duplex(true), Err(Error::PhyTimeout));
+    assert!(!Error::PhyTimeout.is_bus_error());
+    assert_eq!(device.stats().spi_retries, 0);
+}